@@ -0,0 +1,393 @@
+//! Broadcast channel, every receiver gets its own copy of every value
+//!
+//! The last `capacity` values are kept in a ring buffer behind a mutex, each receiver
+//! remembers the sequence number of the next value it wants. A receiver which falls
+//! behind by more than `capacity` values skips the overwritten ones and gets
+//! [`RecvError::Lagged`] once.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::waker::Waiters;
+#[cfg(feature = "async")]
+use crate::waker::{Operation, Waiter};
+
+pub use crate::error::SendError;
+
+/// Error returned by [`Receiver::receive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// all senders are gone and every value was received
+    Closed,
+    /// receiver fell behind, this many values were overwritten before it got them
+    Lagged(u64),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => f.write_str("receiving on a closed channel"),
+            RecvError::Lagged(skipped) => write!(f, "receiver lagged behind by {skipped} values"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+/// Error returned by [`Receiver::try_receive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// no new values yet
+    Empty,
+    /// all senders are gone and every value was received
+    Closed,
+    /// receiver fell behind, this many values were overwritten before it got them
+    Lagged(u64),
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(err: RecvError) -> Self {
+        match err {
+            RecvError::Closed => TryRecvError::Closed,
+            RecvError::Lagged(skipped) => TryRecvError::Lagged(skipped),
+        }
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Closed => RecvError::Closed.fmt(f),
+            TryRecvError::Lagged(skipped) => RecvError::Lagged(*skipped).fmt(f),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+struct Inner<T> {
+    /// last `capacity` values
+    values: VecDeque<T>,
+    /// sequence number of `values[0]`
+    head: u64,
+    receivers: usize,
+    is_closed: bool,
+}
+
+impl<T> Inner<T> {
+    /// Sequence number the next sent value gets
+    fn tail(&self) -> u64 {
+        self.head + self.values.len() as u64
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    capacity: usize,
+    senders: AtomicUsize,
+    /// receivers waiting for the next value
+    waiters: Waiters,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap()
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Returns `Ok` if value is sent or `Err(SendError(value))` if there are no receivers
+    ///
+    /// Never blocks: once the buffer is full the oldest value is overwritten
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.shared.lock();
+        if inner.receivers == 0 {
+            return Err(SendError(value));
+        }
+        inner.values.push_back(value);
+        let overwritten = match inner.values.len() > self.shared.capacity {
+            true => {
+                inner.head += 1;
+                inner.values.pop_front()
+            }
+            false => None,
+        };
+        drop(inner);
+
+        // overwritten value may run arbitrary code in `Drop`, so it is dropped outside the lock
+        drop(overwritten);
+        self.shared.waiters.notify_all();
+        Ok(())
+    }
+
+    /// Creates a receiver which gets values sent after this call
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut inner = self.shared.lock();
+        inner.receivers += 1;
+        let next = inner.tail();
+        drop(inner);
+
+        Receiver::new(Arc::clone(&self.shared), next)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // notifying receivers to stop blocking if this was the last sender
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.lock().is_closed = true;
+            self.shared.waiters.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// sequence number of the next value to receive
+    next: u64,
+    /// registration among blocked receivers while waiting in `poll`
+    #[cfg(feature = "async")]
+    oper: Operation,
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>, next: u64) -> Self {
+        Receiver {
+            shared,
+            next,
+            #[cfg(feature = "async")]
+            oper: Operation::new(),
+        }
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Returns a copy of the next value (will block until it is sent), `Err(RecvError::Lagged(n))`
+    /// if `n` values were overwritten before this receiver got them or `Err(RecvError::Closed)`
+    /// if all senders are gone
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        loop {
+            match self.try_receive() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Closed) => return Err(RecvError::Closed),
+                Err(TryRecvError::Lagged(skipped)) => return Err(RecvError::Lagged(skipped)),
+            }
+            self.shared.waiters.wait(None, || {
+                let inner = self.shared.lock();
+                self.next < inner.tail() || inner.is_closed
+            });
+        }
+    }
+
+    /// Same as [`Receiver::receive`] but returns `Err(TryRecvError::Empty)` instead of blocking
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        let inner = self.shared.lock();
+        if self.next < inner.head {
+            let skipped = inner.head - self.next;
+            self.next = inner.head;
+            return Err(TryRecvError::Lagged(skipped));
+        }
+        match inner.values.get((self.next - inner.head) as usize) {
+            Some(value) => {
+                self.next += 1;
+                Ok(value.clone())
+            }
+            None if inner.is_closed => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: Clone> Receiver<T> {
+    /// Async version of [`Receiver::receive`]
+    pub fn recv_async(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        match self.try_receive() {
+            Err(TryRecvError::Empty) => {}
+            result => return Poll::Ready(result.map_err(into_recv_error)),
+        }
+        self.shared
+            .waiters
+            .register(self.oper, Waiter::Task(cx.waker().clone()));
+        match self.try_receive() {
+            Err(TryRecvError::Empty) => Poll::Pending,
+            result => {
+                self.shared.waiters.unregister(self.oper);
+                Poll::Ready(result.map_err(into_recv_error))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+fn into_recv_error(err: TryRecvError) -> RecvError {
+    match err {
+        TryRecvError::Lagged(skipped) => RecvError::Lagged(skipped),
+        _ => RecvError::Closed,
+    }
+}
+
+/// Future returned by [`Receiver::recv_async`]
+#[cfg(feature = "async")]
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T: Clone> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_receive(cx)
+    }
+}
+
+/// New receiver starts at the same position as this one
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receivers += 1;
+        Receiver::new(Arc::clone(&self.shared), self.next)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "async")]
+        self.shared.waiters.unregister(self.oper);
+        self.shared.lock().receivers -= 1;
+    }
+}
+
+/// Creates a broadcast channel which keeps the last `capacity` values for slow receivers
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be positive");
+
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner {
+            values: VecDeque::with_capacity(capacity),
+            head: 0,
+            receivers: 1,
+            is_closed: false,
+        }),
+        capacity,
+        senders: AtomicUsize::new(1),
+        waiters: Waiters::new(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver::new(shared, 0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_receiver_gets_every_value() {
+        let (tx, mut rx1) = channel(4);
+        let mut rx2 = tx.subscribe();
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(tx.send(2), Ok(()));
+        drop(tx);
+
+        assert_eq!(rx1.receive(), Ok(1));
+        assert_eq!(rx1.receive(), Ok(2));
+        assert_eq!(rx1.receive(), Err(RecvError::Closed));
+        assert_eq!(rx2.try_receive(), Ok(1));
+        assert_eq!(rx2.try_receive(), Ok(2));
+        assert_eq!(rx2.try_receive(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn subscriber_gets_only_new_values() {
+        let (tx, _rx) = channel(4);
+        assert_eq!(tx.send(1), Ok(()));
+        let mut rx = tx.subscribe();
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(tx.send(2), Ok(()));
+        assert_eq!(rx.try_receive(), Ok(2));
+    }
+
+    #[test]
+    fn slow_receiver_lags() {
+        let (tx, mut rx) = channel(2);
+        for i in 0..5 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert_eq!(rx.receive(), Err(RecvError::Lagged(3)));
+        assert_eq!(rx.receive(), Ok(3));
+        assert_eq!(rx.receive(), Ok(4));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn no_receivers() {
+        let (tx, rx) = channel(2);
+        assert_eq!(tx.receiver_count(), 1);
+        drop(rx);
+        assert_eq!(tx.receiver_count(), 0);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn receive_blocks_until_sent() {
+        let (tx, mut rx) = channel(1);
+        let mut rx2 = tx.subscribe();
+        let handles: Vec<_> = [rx.clone(), rx2.clone()]
+            .into_iter()
+            .map(|mut rx| std::thread::spawn(move || rx.receive()))
+            .collect();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(tx.send(1), Ok(()));
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(1));
+        }
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx2.receive(), Ok(1));
+    }
+}
@@ -1 +1,9 @@
+pub mod broadcast;
+mod error;
+pub mod mpmc;
 pub mod mpsc;
+pub mod oneshot;
+pub mod select;
+mod utils;
+mod waker;
+pub mod watch;
@@ -0,0 +1,458 @@
+//! Multi-producer multi-consumer channels
+//!
+//! Same flavors as [`crate::mpsc`] but [`Receiver`] can be cloned, every value is taken by
+//! exactly one of the receivers. Unlike the mpsc receiver it never claims values in batches,
+//! so values ready in the channel are never held back from idle receivers.
+
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::select::{sealed::Handle, SelectRecv};
+#[cfg(feature = "async")]
+use crate::waker::Operation;
+use crate::waker::Waiters;
+
+pub use crate::error::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+pub use crate::mpsc::{Sender, WeakSender};
+
+use crate::mpsc::{self, Shared};
+
+/// Receiving side of a channel, can be cloned to share the values between consumers
+///
+/// Unlike the mpsc receiver it holds no values, so it is `Send` and `Sync` whenever `T: Send`:
+///
+/// ```
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<yk_channel::mpmc::Receiver<std::cell::Cell<i32>>>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<yk_channel::mpmc::Receiver<std::rc::Rc<i32>>>();
+/// ```
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// registration among blocked receivers while waiting in `poll`
+    #[cfg(feature = "async")]
+    oper: Operation,
+    #[cfg(feature = "async")]
+    queued: bool,
+}
+
+impl<T> Receiver<T> {
+    /// Returns `Ok(value)` when value is available (will block if channel is empty) or `Err(RecvError)` if channel is closed
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        self.shared.flavor.recv(None).map_err(|_| RecvError)
+    }
+
+    /// Same as [`Receiver::receive`] but returns `Err(TryRecvError::Empty)` instead of blocking
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        self.shared.flavor.try_recv()
+    }
+
+    /// Same as [`Receiver::receive`] but gives up after waiting for `timeout`
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // deadline may be too far in the future to ever be reached
+        self.shared.flavor.recv(Instant::now().checked_add(timeout))
+    }
+
+    /// Same as [`Receiver::receive`] but gives up once `deadline` is reached
+    pub fn receive_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.shared.flavor.recv(Some(deadline))
+    }
+
+    /// Number of values sent but not received yet
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `None` for unbounded channels, `Some(0)` for rendezvous ones
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.flavor.capacity()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting new values for every receiver, the ones already sent can still be received
+    pub fn close(&mut self) {
+        self.shared.disconnect();
+    }
+
+    /// Returns whether the channel is closed or all senders are gone, remaining values
+    /// can still be received
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// Blocking iterator over received values, ends once channel is closed
+    pub fn iter(&mut self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    /// Iterator over values which can be received without blocking
+    pub fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Receiver<T> {
+    /// Async version of [`Receiver::receive`]
+    ///
+    /// Waiting receivers get values in FIFO order. Dropping the future gives up its turn
+    /// without losing a value that was already sent for it
+    pub fn recv_async(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        let flavor = &self.shared.flavor;
+        // rendezvous receivers wait where `try_send` can hand values to them
+        if let mpsc::Flavor::Zero(chan) = flavor {
+            return chan.poll_recv(self.oper, cx);
+        }
+        flavor
+            .receivers()
+            .poll(self.oper, &mut self.queued, cx, || {
+                match flavor.try_recv() {
+                    Err(TryRecvError::Empty) => None,
+                    result => Some(result.map_err(|_| RecvError)),
+                }
+            })
+    }
+
+    fn cancel_receive(&mut self) {
+        match &self.shared.flavor {
+            mpsc::Flavor::Zero(chan) => chan.cancel_recv(self.oper),
+            flavor => flavor.receivers().cancel(self.oper, &mut self.queued),
+        }
+    }
+}
+
+/// Future returned by [`Receiver::recv_async`]
+#[cfg(feature = "async")]
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_receive(cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Drop for RecvFuture<'_, T> {
+    fn drop(&mut self) {
+        self.receiver.cancel_receive();
+    }
+}
+
+// values are never pinned, so receiver can be moved freely between polls
+#[cfg(feature = "async")]
+impl<T> Unpin for Receiver<T> {}
+
+/// Yields values until channel is closed
+#[cfg(feature = "async")]
+impl<T> futures_core::Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_receive(cx).map(Result::ok)
+    }
+}
+
+/// Iterator returned by [`Receiver::iter`]
+pub struct Iter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+/// Iterator returned by [`Receiver::try_iter`]
+pub struct TryIter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_receive().ok()
+    }
+}
+
+/// Owning blocking iterator, ends once channel is closed
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+
+        Receiver {
+            shared: Arc::clone(&self.shared),
+            #[cfg(feature = "async")]
+            oper: Operation::new(),
+            #[cfg(feature = "async")]
+            queued: false,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "async")]
+        self.cancel_receive();
+
+        // notifying senders to stop blocking if this was the last receiver
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.disconnect();
+            self.shared.flavor.drain();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Takes over the channel of a fresh mpsc receiver
+    fn from_mpsc(receiver: mpsc::Receiver<T>) -> Self {
+        Receiver {
+            shared: receiver.into_shared(),
+            #[cfg(feature = "async")]
+            oper: Operation::new(),
+            #[cfg(feature = "async")]
+            queued: false,
+        }
+    }
+}
+
+impl<T> Handle for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.shared.flavor.can_recv()
+    }
+
+    fn waiters(&self) -> Option<&Waiters> {
+        Some(self.shared.flavor.receivers())
+    }
+}
+
+impl<T> SelectRecv for Receiver<T> {}
+
+/// Creates an unbounded mpmc channel
+pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (tx, Receiver::from_mpsc(rx))
+}
+
+/// Creates a bounded mpmc channel which holds at most `capacity` values
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero
+pub fn bounded_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::bounded_channel(capacity);
+    (tx, Receiver::from_mpsc(rx))
+}
+
+/// Creates an mpmc channel without a buffer: [`Sender::send`] blocks until
+/// one of the receivers takes the value
+pub fn rendezvous_channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpsc::rendezvous_channel();
+    (tx, Receiver::from_mpsc(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let (tx, mut rx) = unbounded_channel();
+        assert_eq!(tx.send(5), Ok(()));
+        assert_eq!(rx.receive(), Ok(5));
+        drop(tx);
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn iterators() {
+        let (tx, mut rx) = bounded_channel(4);
+        for i in 0..3 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(tx.send(3), Ok(()));
+        drop(tx);
+        let mut received = Vec::new();
+        for value in &mut rx {
+            received.push(value);
+        }
+        assert_eq!(received, [3]);
+    }
+
+    #[test]
+    fn close() {
+        let (tx, mut rx) = unbounded_channel();
+        let rx2 = rx.clone();
+        assert_eq!(tx.send(1), Ok(()));
+        rx.close();
+        assert!(rx2.is_closed());
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Err(RecvError));
+        tx.closed();
+    }
+
+    #[test]
+    fn pending_values_are_dropped_with_last_receiver() {
+        let value = Arc::new(());
+        for (tx, rx) in [unbounded_channel(), bounded_channel(4)] {
+            let rx2 = rx.clone();
+            for _ in 0..3 {
+                assert_eq!(tx.send(Arc::clone(&value)), Ok(()));
+            }
+            drop(rx);
+            assert_eq!(Arc::strong_count(&value), 4);
+            drop(rx2);
+            assert_eq!(Arc::strong_count(&value), 1);
+        }
+    }
+
+    #[test]
+    fn rx_closed_when_all_dropped() {
+        let (tx, rx) = bounded_channel(1);
+        let rx2 = rx.clone();
+        drop(rx);
+        assert_eq!(tx.try_send(1), Ok(()));
+        drop(rx2);
+        assert_eq!(tx.try_send(2), Err(TrySendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn every_value_is_received_once() {
+        const RECEIVERS: usize = 4;
+        const VALUES: usize = if cfg!(miri) { 50 } else { 10_000 };
+
+        for (tx, rx) in [
+            unbounded_channel(),
+            bounded_channel(4),
+            rendezvous_channel(),
+        ] {
+            let handles: Vec<_> = (0..RECEIVERS)
+                .map(|_| {
+                    let mut rx = rx.clone();
+                    std::thread::spawn(move || {
+                        let mut received = Vec::new();
+                        while let Ok(value) = rx.receive() {
+                            received.push(value);
+                        }
+                        received
+                    })
+                })
+                .collect();
+            drop(rx);
+
+            for i in 0..VALUES {
+                assert_eq!(tx.send(i), Ok(()));
+            }
+            drop(tx);
+
+            let mut received: Vec<usize> = handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect();
+            received.sort();
+            assert_eq!(received, (0..VALUES).collect::<Vec<_>>());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn cancelled_recv_async_passes_wakeup_on() {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            task::{Wake, Waker},
+        };
+
+        struct FlagWaker(AtomicBool);
+
+        impl Wake for FlagWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (tx, mut rx1) = unbounded_channel();
+        let mut rx2 = rx1.clone();
+        let flag1 = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let flag2 = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let waker1 = Waker::from(Arc::clone(&flag1));
+        let waker2 = Waker::from(Arc::clone(&flag2));
+        let mut cx1 = Context::from_waker(&waker1);
+        let mut cx2 = Context::from_waker(&waker2);
+
+        let mut first = rx1.recv_async();
+        assert!(Pin::new(&mut first).poll(&mut cx1).is_pending());
+        let mut second = rx2.recv_async();
+        assert!(Pin::new(&mut second).poll(&mut cx2).is_pending());
+
+        assert_eq!(tx.send(1), Ok(()));
+        assert!(flag1.0.load(Ordering::SeqCst));
+        assert!(!flag2.0.load(Ordering::SeqCst));
+
+        drop(first);
+        assert!(flag2.0.load(Ordering::SeqCst));
+        assert_eq!(Pin::new(&mut second).poll(&mut cx2), Poll::Ready(Ok(1)));
+    }
+}
@@ -1,171 +1,1960 @@
 use std::{
     collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::select::{sealed::Handle, SelectRecv, SelectSend};
+use crate::waker::Waiters;
+#[cfg(feature = "async")]
+use crate::waker::{Operation, Waiter};
+
+pub use crate::error::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+mod array;
+mod fair;
+mod heap;
+mod list;
+mod timer;
+mod zero;
+
+/// Sending side of a channel, can be cloned or shared by reference between threads
+///
+/// `Sender<T>` is `Send` and `Sync` whenever `T: Send`:
+///
+/// ```
+/// fn assert_send_sync<T: Send + Sync>() {}
+/// assert_send_sync::<yk_channel::mpsc::Sender<std::cell::Cell<i32>>>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<yk_channel::mpsc::Sender<std::rc::Rc<i32>>>();
+/// ```
 pub struct Sender<T> {
     shared: Arc<Shared<T>>,
+    /// own queue in a fair channel, unused by other flavors
+    id: usize,
+    #[cfg(feature = "async")]
+    sink: SinkState<T>,
+}
+
+// value buffered by the `Sink` impl is only touched through `&mut Sender`
+#[cfg(feature = "async")]
+unsafe impl<T: Send> Sync for Sender<T> {}
+
+/// Bookkeeping of [`futures_sink::Sink`] impl between polls
+#[cfg(feature = "async")]
+struct SinkState<T> {
+    /// registration among blocked senders while waiting for a free slot
+    oper: Operation,
+    queued: bool,
+    /// value passed to `start_send` which didn't fit into the channel yet
+    pending: Option<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Default for SinkState<T> {
+    fn default() -> Self {
+        SinkState {
+            oper: Operation::new(),
+            queued: false,
+            pending: None,
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// returns `Ok` is value is sent or `Err(SendError(value))` if receiver is dropped
+    ///
+    /// For bounded channels blocks while the channel is full, for rendezvous channels
+    /// blocks until the receiver takes the value
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match &self.shared.flavor {
+            Flavor::List(chan) => chan.send(value),
+            Flavor::Fair(chan) => chan.send(self.id, value),
+            Flavor::Priority(chan) => chan.send(0, value),
+            Flavor::Delay(chan) => chan.send(heap::Due::now(), value),
+            Flavor::Array(chan) => chan.send(value),
+            Flavor::Zero(chan) => chan.send(value),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        }
+    }
+
+    /// Same as [`Sender::send`] but returns `Err(TrySendError::Full(value))` instead of blocking
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match &self.shared.flavor {
+            Flavor::List(chan) => Ok(chan.send(value)?),
+            Flavor::Fair(chan) => Ok(chan.send(self.id, value)?),
+            Flavor::Priority(chan) => Ok(chan.send(0, value)?),
+            Flavor::Delay(chan) => Ok(chan.send(heap::Due::now(), value)?),
+            Flavor::Array(chan) => chan.try_send(value),
+            Flavor::Zero(chan) => chan.try_send(value),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        }
+    }
+
+    /// Same as [`Sender::send`], but a [`priority_channel`] delivers values with a higher
+    /// `priority` first. [`Sender::send`] uses the lowest priority. Other channels ignore it
+    pub fn send_with_priority(&self, value: T, priority: u32) -> Result<(), SendError<T>> {
+        match &self.shared.flavor {
+            Flavor::Priority(chan) => chan.send(priority, value),
+            _ => self.send(value),
+        }
+    }
+
+    /// Number of values sent but not received yet
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `None` for unbounded channels, `Some(0)` for rendezvous ones
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.flavor.capacity()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    pub fn weak_sender_count(&self) -> usize {
+        self.shared.weak_senders.load(Ordering::Relaxed)
+    }
+
+    /// Creates a [`WeakSender`] which doesn't keep the channel open
+    pub fn downgrade(&self) -> WeakSender<T> {
+        self.shared.weak_senders.fetch_add(1, Ordering::Relaxed);
+
+        WeakSender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Returns whether the receiver is dropped or closed, so sending would fail
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// Blocks until the receiver is dropped or closed
+    pub fn closed(&self) {
+        self.shared.wait_closed()
+    }
+
+    /// Sends values in order, blocking the same way as [`Sender::send`]. Returns the values
+    /// which weren't sent if receiver is dropped
+    ///
+    /// For unbounded channels blocked receivers are woken up once for the whole batch
+    pub fn send_all(&self, values: impl IntoIterator<Item = T>) -> Result<(), SendError<Vec<T>>> {
+        let mut values = values.into_iter();
+        if let Flavor::List(chan) = &self.shared.flavor {
+            return chan.send_all(values);
+        }
+        while let Some(value) = values.next() {
+            if let Err(SendError(value)) = self.send(value) {
+                return Err(SendError(std::iter::once(value).chain(values).collect()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+
+        Sender {
+            shared: Arc::clone(&self.shared),
+            id: self.shared.flavor.new_sender_id(),
+            #[cfg(feature = "async")]
+            sink: SinkState::default(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "async")]
+        self.release_sink();
+
+        // notifying receiver to stop blocking if this was the last sender
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.disconnect();
+        }
+    }
+}
+
+/// Sending side of a [`delay_channel`], which can also hold values back until they are due
+///
+/// Derefs to [`Sender`], values sent through its methods are due right away. Other
+/// channels can't delay values:
+///
+/// ```compile_fail
+/// let (tx, _rx) = yk_channel::mpsc::unbounded_channel();
+/// tx.send_after(1, std::time::Duration::from_secs(1));
+/// ```
+pub struct DelaySender<T> {
+    sender: Sender<T>,
+}
+
+impl<T> DelaySender<T> {
+    /// Same as [`Sender::send`] but the value isn't delivered until `delay` passes. A delay
+    /// too long to be represented as an [`Instant`] means never
+    pub fn send_after(&self, value: T, delay: Duration) -> Result<(), SendError<T>> {
+        self.send_due(value, heap::Due(Instant::now().checked_add(delay)))
+    }
+
+    /// Same as [`DelaySender::send_after`] but the value is delivered once `due` is reached
+    pub fn send_at(&self, value: T, due: Instant) -> Result<(), SendError<T>> {
+        self.send_due(value, heap::Due(Some(due)))
+    }
+
+    fn send_due(&self, value: T, due: heap::Due) -> Result<(), SendError<T>> {
+        match &self.sender.shared.flavor {
+            Flavor::Delay(chan) => chan.send(due, value),
+            _ => unreachable!("delay senders are created by `delay_channel`"),
+        }
+    }
+}
+
+impl<T> Clone for DelaySender<T> {
+    fn clone(&self) -> Self {
+        DelaySender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> std::ops::Deref for DelaySender<T> {
+    type Target = Sender<T>;
+
+    fn deref(&self) -> &Sender<T> {
+        &self.sender
+    }
+}
+
+impl<T> std::ops::DerefMut for DelaySender<T> {
+    fn deref_mut(&mut self) -> &mut Sender<T> {
+        &mut self.sender
+    }
+}
+
+/// Sender which doesn't count towards [`Receiver`] seeing the channel closed
+///
+/// Once every [`Sender`] is dropped the channel disconnects and [`WeakSender::upgrade`]
+/// keeps returning `None`, even if the receiver is still around
+pub struct WeakSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> WeakSender<T> {
+    /// Returns a [`Sender`] unless all of them are already dropped
+    pub fn upgrade(&self) -> Option<Sender<T>> {
+        let mut senders = self.shared.senders.load(Ordering::Relaxed);
+        loop {
+            // the channel was disconnected when the last sender went away
+            if senders == 0 {
+                return None;
+            }
+            match self.shared.senders.compare_exchange_weak(
+                senders,
+                senders + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => senders = current,
+            }
+        }
+
+        Some(Sender {
+            shared: Arc::clone(&self.shared),
+            id: self.shared.flavor.new_sender_id(),
+            #[cfg(feature = "async")]
+            sink: SinkState::default(),
+        })
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    pub fn weak_sender_count(&self) -> usize {
+        self.shared.weak_senders.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for WeakSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.weak_senders.fetch_add(1, Ordering::Relaxed);
+
+        WeakSender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
 }
 
-pub struct ChannelClosedError;
+impl<T> Drop for WeakSender<T> {
+    fn drop(&mut self) {
+        self.shared.weak_senders.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Sender<T> {
+    /// Async version of [`Sender::send`], yields instead of blocking while bounded channel is full
+    ///
+    /// Waiting senders get free slots in FIFO order. Dropping the future gives up its turn
+    /// without losing a slot that was already freed for it
+    pub fn send_async(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            value: Some(value),
+            oper: Operation::new(),
+            queued: false,
+        }
+    }
+
+    /// Async version of [`Sender::closed`]
+    pub fn closed_async(&self) -> ClosedFuture<'_, T> {
+        ClosedFuture {
+            shared: &self.shared,
+            oper: Operation::new(),
+        }
+    }
+
+    fn release_sink(&mut self) {
+        match &self.shared.flavor {
+            Flavor::List(_) | Flavor::Fair(_) | Flavor::Priority(_) | Flavor::Delay(_) => {}
+            Flavor::Array(chan) => chan.cancel_send(self.sink.oper, &mut self.sink.queued),
+            Flavor::Zero(chan) => chan.cancel_send(self.sink.oper, &mut self.sink.queued),
+            Flavor::Timer(_) => {}
+        }
+    }
+
+    /// Pushes the value buffered by `start_send`, if any
+    fn poll_flush_sink(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SendError<()>>> {
+        let SinkState {
+            oper,
+            queued,
+            pending,
+        } = &mut self.sink;
+        if pending.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+        let result = match &self.shared.flavor {
+            Flavor::List(chan) => Poll::Ready(chan.send(pending.take().unwrap())),
+            Flavor::Fair(chan) => Poll::Ready(chan.send(self.id, pending.take().unwrap())),
+            Flavor::Priority(chan) => Poll::Ready(chan.send(0, pending.take().unwrap())),
+            Flavor::Delay(chan) => {
+                Poll::Ready(chan.send(heap::Due::now(), pending.take().unwrap()))
+            }
+            Flavor::Array(chan) => chan.poll_send(pending, *oper, queued, cx),
+            Flavor::Zero(chan) => chan.poll_send(pending, *oper, queued, cx),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        };
+        result.map(|result| result.map_err(|_| SendError(())))
+    }
+}
+
+/// Future returned by [`Sender::send_async`]
+#[cfg(feature = "async")]
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    /// registration among blocked senders while waiting for a free slot or a receiver
+    oper: Operation,
+    queued: bool,
+}
+
+/// Future returned by [`Sender::closed_async`]
+#[cfg(feature = "async")]
+pub struct ClosedFuture<'a, T> {
+    shared: &'a Shared<T>,
+    /// registration among senders waiting for the channel to close
+    oper: Operation,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for ClosedFuture<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.shared.poll_closed(self.oper, cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Drop for ClosedFuture<'_, T> {
+    fn drop(&mut self) {
+        self.shared.closed.unregister(self.oper);
+    }
+}
+
+// value is never pinned
+#[cfg(feature = "async")]
+impl<T> Unpin for SendFuture<'_, T> {}
+
+#[cfg(feature = "async")]
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match &this.sender.shared.flavor {
+            Flavor::List(chan) => {
+                let value = this.value.take().expect("future polled after completion");
+                Poll::Ready(chan.send(value))
+            }
+            Flavor::Fair(chan) => {
+                let value = this.value.take().expect("future polled after completion");
+                Poll::Ready(chan.send(this.sender.id, value))
+            }
+            Flavor::Priority(chan) => {
+                let value = this.value.take().expect("future polled after completion");
+                Poll::Ready(chan.send(0, value))
+            }
+            Flavor::Delay(chan) => {
+                let value = this.value.take().expect("future polled after completion");
+                Poll::Ready(chan.send(heap::Due::now(), value))
+            }
+            Flavor::Array(chan) => chan.poll_send(&mut this.value, this.oper, &mut this.queued, cx),
+            Flavor::Zero(chan) => chan.poll_send(&mut this.value, this.oper, &mut this.queued, cx),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        match &self.sender.shared.flavor {
+            Flavor::List(_) | Flavor::Fair(_) | Flavor::Priority(_) | Flavor::Delay(_) => {}
+            Flavor::Array(chan) => chan.cancel_send(self.oper, &mut self.queued),
+            Flavor::Zero(chan) => chan.cancel_send(self.oper, &mut self.queued),
+            Flavor::Timer(_) => {}
+        }
+    }
+}
+
+// buffered value is never pinned
+#[cfg(feature = "async")]
+impl<T> Unpin for Sender<T> {}
+
+/// `poll_ready` waits for a free slot of a bounded channel (or a waiting receiver of a
+/// rendezvous one). If another sender takes it first, `start_send` keeps the value and it
+/// is pushed by the following `poll_ready` or `poll_flush`
+#[cfg(feature = "async")]
+impl<T> futures_sink::Sink<T> for Sender<T> {
+    type Error = SendError<()>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.sink.pending.is_some() {
+            match this.poll_flush_sink(cx) {
+                Poll::Ready(Ok(())) => {}
+                result => return result,
+            }
+        }
+        let flavor = &this.shared.flavor;
+        if let Some(senders) = flavor.senders() {
+            let SinkState { oper, queued, .. } = &mut this.sink;
+            let can_send = || flavor.can_send().then_some(());
+            if senders.poll(*oper, queued, cx, can_send).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(match flavor.is_disconnected() {
+            true => Err(SendError(())),
+            false => Ok(()),
+        })
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let result = match &this.shared.flavor {
+            Flavor::List(chan) => chan.send(item).map_err(TrySendError::from),
+            Flavor::Fair(chan) => chan.send(this.id, item).map_err(TrySendError::from),
+            Flavor::Priority(chan) => chan.send(0, item).map_err(TrySendError::from),
+            Flavor::Delay(chan) => chan
+                .send(heap::Due::now(), item)
+                .map_err(TrySendError::from),
+            Flavor::Array(chan) => chan.try_send(item),
+            Flavor::Zero(chan) => chan.try_send(item),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) => {
+                this.sink.pending = Some(item);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(SendError(())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_sink(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_sink(cx)
+    }
+}
+
+/// Receiving side of a channel
+///
+/// `Receiver<T>` is `Send` whenever `T: Send`, but it holds claimed values, so it is
+/// `Sync` only if `T: Sync` as well:
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<yk_channel::mpsc::Receiver<std::cell::Cell<i32>>>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<yk_channel::mpsc::Receiver<std::rc::Rc<i32>>>();
+/// ```
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// values already claimed from the channel but not returned yet
+    buffer: VecDeque<T>,
+    /// registration among blocked receivers while waiting in `poll`
+    #[cfg(feature = "async")]
+    oper: Operation,
+}
+
+impl<T> Receiver<T> {
+    /// Returns `Ok(value)` when value is available (will block if channel is empty) or `Err(RecvError)` if channel is closed
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        self.receive_until(None).map_err(|_| RecvError)
+    }
+
+    /// Same as [`Receiver::receive`] but returns `Err(TryRecvError::Empty)` instead of blocking
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.pop_buffer() {
+            return Ok(value);
+        }
+
+        let result = match &self.shared.flavor {
+            Flavor::List(chan) => chan.try_recv_batch(&mut self.buffer),
+            Flavor::Fair(chan) => chan.try_recv(),
+            Flavor::Priority(chan) => chan.try_recv(),
+            Flavor::Delay(chan) => chan.try_recv(),
+            Flavor::Array(chan) => chan.try_recv(),
+            Flavor::Zero(chan) => chan.try_recv(),
+            Flavor::Timer(chan) => chan.try_recv(),
+        };
+        self.update_buffered();
+        result
+    }
+
+    /// Same as [`Receiver::receive`] but gives up after waiting for `timeout`
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // deadline may be too far in the future to ever be reached
+        self.receive_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Same as [`Receiver::receive`] but gives up once `deadline` is reached
+    pub fn receive_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(Some(deadline))
+    }
+
+    /// Appends up to `max` values to `values`, blocking only while there is none.
+    /// Returns how many were received or `Err(RecvError)` if channel is closed
+    pub fn receive_many(&mut self, values: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        if self.buffer.is_empty() {
+            let first = self.receive()?;
+            self.buffer.push_front(first);
+        }
+        let len = values.len();
+        while values.len() - len < max {
+            if self.buffer.is_empty() {
+                match self.try_receive() {
+                    Ok(value) => values.push(value),
+                    Err(_) => break,
+                }
+            } else {
+                let count = self.buffer.len().min(max - (values.len() - len));
+                values.extend(self.buffer.drain(..count));
+            }
+        }
+        self.update_buffered();
+        Ok(values.len() - len)
+    }
+
+    /// Number of values sent but not received yet
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `None` for unbounded channels, `Some(0)` for rendezvous ones
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.flavor.capacity()
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.shared.senders.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting new values, the ones already sent can still be received
+    pub fn close(&mut self) {
+        self.shared.disconnect();
+    }
+
+    /// Returns whether the channel is closed or all senders are gone, remaining values
+    /// can still be received
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// Blocking iterator over received values, ends once channel is closed
+    pub fn iter(&mut self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    /// Iterator over values which can be received without blocking, claimed ones first
+    pub fn try_iter(&mut self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
+    fn receive_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if let Some(value) = self.pop_buffer() {
+            return Ok(value);
+        }
+
+        let result = match &self.shared.flavor {
+            Flavor::List(chan) => chan.recv_batch(&mut self.buffer, deadline),
+            Flavor::Fair(chan) => chan.recv(deadline),
+            Flavor::Priority(chan) => chan.recv(deadline),
+            Flavor::Delay(chan) => chan.recv(deadline),
+            Flavor::Array(chan) => chan.recv(deadline),
+            Flavor::Zero(chan) => chan.recv(deadline),
+            Flavor::Timer(chan) => chan.recv(deadline),
+        };
+        self.update_buffered();
+        result
+    }
+
+    fn pop_buffer(&mut self) -> Option<T> {
+        let value = self.buffer.pop_front()?;
+        self.update_buffered();
+        Some(value)
+    }
+
+    /// Makes values claimed into the buffer visible to senders' [`Sender::len`]
+    fn update_buffered(&self) {
+        self.shared
+            .buffered
+            .store(self.buffer.len(), Ordering::Relaxed);
+    }
+
+    /// Takes the channel out of the receiver without disconnecting it. Claimed values would
+    /// be lost, so it is only used on receivers which haven't received anything
+    pub(crate) fn into_shared(self) -> Arc<Shared<T>> {
+        debug_assert!(self.buffer.is_empty(), "receiver already claimed values");
+        let mut this = std::mem::ManuallyDrop::new(self);
+        #[cfg(feature = "async")]
+        this.shared.flavor.cancel_recv(this.oper);
+        drop(std::mem::take(&mut this.buffer));
+        this.update_buffered();
+        unsafe { std::ptr::read(&this.shared) }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Receiver<T> {
+    /// Async version of [`Receiver::receive`]
+    pub fn recv_async(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        if let Some(value) = self.pop_buffer() {
+            return Poll::Ready(Ok(value));
+        }
+
+        let result = match &self.shared.flavor {
+            Flavor::List(chan) => chan.poll_recv_batch(&mut self.buffer, self.oper, cx),
+            Flavor::Fair(chan) => chan.poll_recv(self.oper, cx),
+            Flavor::Priority(chan) => chan.poll_recv(self.oper, cx),
+            Flavor::Delay(chan) => chan.poll_recv(self.oper, cx),
+            Flavor::Array(chan) => chan.poll_recv(self.oper, cx),
+            Flavor::Zero(chan) => chan.poll_recv(self.oper, cx),
+            Flavor::Timer(chan) => chan.poll_recv(cx),
+        };
+        self.update_buffered();
+        result
+    }
+}
+
+/// Future returned by [`Receiver::recv_async`]
+#[cfg(feature = "async")]
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_receive(cx)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Drop for RecvFuture<'_, T> {
+    fn drop(&mut self) {
+        self.receiver.shared.flavor.cancel_recv(self.receiver.oper);
+    }
+}
+
+// values are never pinned, so receiver can be moved freely between polls
+#[cfg(feature = "async")]
+impl<T> Unpin for Receiver<T> {}
+
+/// Yields values until channel is closed
+#[cfg(feature = "async")]
+impl<T> futures_core::Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_receive(cx).map(Result::ok)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "async")]
+        self.shared.flavor.cancel_recv(self.oper);
+        self.shared.disconnect();
+
+        // nobody can receive the rest, so it shouldn't wait for the last sender to be dropped
+        self.buffer.clear();
+        self.update_buffered();
+        self.shared.flavor.drain();
+    }
+}
+
+/// Iterator returned by [`Receiver::iter`]
+pub struct Iter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+/// Iterator returned by [`Receiver::try_iter`]
+pub struct TryIter<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_receive().ok()
+    }
+}
+
+/// Owning blocking iterator, ends once channel is closed
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// `Send` and `Sync` whenever `T: Send`, values are only ever moved between threads
+pub(crate) struct Shared<T> {
+    pub(crate) senders: AtomicUsize,
+    /// [`WeakSender`]s, which don't keep the channel open
+    pub(crate) weak_senders: AtomicUsize,
+    /// always 1 for mpsc, counted by cloneable [`crate::mpmc::Receiver`]
+    pub(crate) receivers: AtomicUsize,
+    pub(crate) flavor: Flavor<T>,
+    /// values claimed into the mpsc receiver's buffer
+    pub(crate) buffered: AtomicUsize,
+    /// senders waiting in [`Sender::closed`]
+    pub(crate) closed: Waiters,
+}
+
+impl<T> Shared<T> {
+    /// Disconnects the channel and wakes up everyone waiting for it
+    pub(crate) fn disconnect(&self) {
+        if self.flavor.disconnect() {
+            self.closed.notify_all();
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.flavor.is_disconnected()
+    }
+
+    /// Values sent but not received yet, including the claimed ones
+    pub(crate) fn len(&self) -> usize {
+        self.flavor.len() + self.buffered.load(Ordering::Relaxed)
+    }
+
+    /// Blocks until channel is disconnected
+    pub(crate) fn wait_closed(&self) {
+        while !self.is_closed() {
+            self.closed.wait(None, || self.is_closed());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_closed(&self, oper: Operation, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_closed() {
+            return Poll::Ready(());
+        }
+        self.closed.register(oper, Waiter::Task(cx.waker().clone()));
+        if self.is_closed() {
+            self.closed.unregister(oper);
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+// lives in `Shared` behind an `Arc`, so size difference between flavors doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Flavor<T> {
+    /// lock-free linked list of blocks
+    List(list::Channel<T>),
+    /// mutex-protected queue per sender, served round-robin
+    Fair(fair::Channel<T>),
+    /// mutex-protected heap, highest priority first
+    Priority(heap::Channel<u32, T>),
+    /// mutex-protected heap, earliest due first
+    Delay(heap::Channel<heap::Due, T>),
+    /// lock-free ring buffer with a capacity limit
+    Array(array::Channel<T>),
+    /// hand-off without a buffer
+    Zero(zero::Channel<T>),
+    /// values produced on schedule, without senders
+    Timer(timer::Channel<T>),
+}
+
+/// Operations receiving one value at a time, for receivers which don't own a buffer
+impl<T> Flavor<T> {
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self {
+            Flavor::List(chan) => chan.try_recv(),
+            Flavor::Fair(chan) => chan.try_recv(),
+            Flavor::Priority(chan) => chan.try_recv(),
+            Flavor::Delay(chan) => chan.try_recv(),
+            Flavor::Array(chan) => chan.try_recv(),
+            Flavor::Zero(chan) => chan.try_recv(),
+            Flavor::Timer(chan) => chan.try_recv(),
+        }
+    }
+
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        match self {
+            Flavor::List(chan) => chan.recv(deadline),
+            Flavor::Fair(chan) => chan.recv(deadline),
+            Flavor::Priority(chan) => chan.recv(deadline),
+            Flavor::Delay(chan) => chan.recv(deadline),
+            Flavor::Array(chan) => chan.recv(deadline),
+            Flavor::Zero(chan) => chan.recv(deadline),
+            Flavor::Timer(chan) => chan.recv(deadline),
+        }
+    }
+
+    /// Receivers blocked on an empty channel
+    pub(crate) fn receivers(&self) -> &Waiters {
+        match self {
+            Flavor::List(chan) => &chan.receivers,
+            Flavor::Fair(chan) => &chan.receivers,
+            Flavor::Priority(chan) => &chan.receivers,
+            Flavor::Delay(chan) => &chan.receivers,
+            Flavor::Array(chan) => &chan.receivers,
+            Flavor::Zero(chan) => &chan.receivers,
+            Flavor::Timer(chan) => &chan.receivers,
+        }
+    }
+
+    /// Senders blocked on a full channel, `None` if sending never blocks
+    pub(crate) fn senders(&self) -> Option<&Waiters> {
+        match self {
+            Flavor::List(_) | Flavor::Fair(_) | Flavor::Priority(_) | Flavor::Delay(_) => None,
+            Flavor::Array(chan) => Some(&chan.senders),
+            Flavor::Zero(chan) => Some(&chan.senders),
+            Flavor::Timer(_) => None,
+        }
+    }
+
+    /// Whether receiving wouldn't block
+    pub(crate) fn can_recv(&self) -> bool {
+        match self {
+            Flavor::List(chan) => !chan.is_empty() || chan.is_disconnected(),
+            Flavor::Fair(chan) => !chan.is_empty() || chan.is_disconnected(),
+            Flavor::Priority(chan) => !chan.is_empty() || chan.is_disconnected(),
+            Flavor::Delay(chan) => chan.can_recv(),
+            Flavor::Array(chan) => !chan.is_empty() || chan.is_disconnected(),
+            Flavor::Zero(chan) => !chan.is_empty() || chan.is_disconnected(),
+            Flavor::Timer(chan) => !chan.is_empty(),
+        }
+    }
+
+    /// Whether `try_send` wouldn't fail with `Full`
+    pub(crate) fn can_send(&self) -> bool {
+        match self {
+            Flavor::List(_) | Flavor::Fair(_) | Flavor::Priority(_) | Flavor::Delay(_) => true,
+            Flavor::Array(chan) => !chan.is_full() || chan.is_disconnected(),
+            Flavor::Zero(chan) => chan.has_waiting_receivers() || chan.is_disconnected(),
+            Flavor::Timer(_) => unreachable!("timer channels have no senders"),
+        }
+    }
+
+    /// Values in the channel, not counting the ones claimed by the mpsc receiver
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Flavor::List(chan) => chan.len(),
+            Flavor::Fair(chan) => chan.len(),
+            Flavor::Priority(chan) => chan.len(),
+            Flavor::Delay(chan) => chan.len(),
+            Flavor::Array(chan) => chan.len(),
+            Flavor::Zero(chan) => chan.len(),
+            Flavor::Timer(chan) => usize::from(!chan.is_empty()),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        match self {
+            Flavor::List(_) | Flavor::Fair(_) | Flavor::Priority(_) | Flavor::Delay(_) => None,
+            Flavor::Array(chan) => Some(chan.capacity()),
+            Flavor::Zero(_) => Some(0),
+            Flavor::Timer(chan) => Some(chan.capacity()),
+        }
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        match self {
+            Flavor::List(chan) => chan.is_disconnected(),
+            Flavor::Fair(chan) => chan.is_disconnected(),
+            Flavor::Priority(chan) => chan.is_disconnected(),
+            Flavor::Delay(chan) => chan.is_disconnected(),
+            Flavor::Array(chan) => chan.is_disconnected(),
+            Flavor::Zero(chan) => chan.is_disconnected(),
+            Flavor::Timer(_) => false,
+        }
+    }
+
+    /// Drops values left in a disconnected channel. Offers of blocked senders of a
+    /// rendezvous channel stay, the senders take them back
+    pub(crate) fn drain(&self) {
+        match self {
+            Flavor::List(chan) => while chan.try_recv().is_ok() {},
+            Flavor::Fair(chan) => chan.drain(),
+            Flavor::Priority(chan) => chan.drain(),
+            Flavor::Delay(chan) => chan.drain(),
+            Flavor::Array(chan) => while chan.try_recv().is_ok() {},
+            Flavor::Zero(chan) => chan.drain(),
+            Flavor::Timer(_) => {}
+        }
+    }
+
+    /// Stops waiting as `oper` started by polling a receive
+    #[cfg(feature = "async")]
+    pub(crate) fn cancel_recv(&self, oper: Operation) {
+        match self {
+            Flavor::Zero(chan) => chan.cancel_recv(oper),
+            _ => {
+                self.receivers().unregister(oper);
+            }
+        }
+    }
+
+    /// Id of a new sender, only fair channels tell senders apart
+    pub(crate) fn new_sender_id(&self) -> usize {
+        match self {
+            Flavor::Fair(chan) => chan.new_sender_id(),
+            _ => 0,
+        }
+    }
 
-impl<T> Sender<T> {
-    /// returns `Ok` is value is sent or `Err(value)` if receiver is dropped
-    pub fn send(&mut self, value: T) -> Result<(), T> {
-        let mut inner = self.shared.inner.lock().unwrap();
-        if !inner.receiver_alive {
-            return Err(value);
-        }
-        inner.queue.push_back(value);
-        drop(inner);
-        self.shared.can_receive.notify_one();
-        Ok(())
+    /// When a timer or a delayed value is due next, other flavors are woken up by senders
+    pub(crate) fn due(&self) -> Option<Instant> {
+        match self {
+            Flavor::Delay(chan) => chan.next_due(),
+            Flavor::Timer(chan) => chan.due(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn disconnect(&self) -> bool {
+        match self {
+            Flavor::List(chan) => chan.disconnect(),
+            Flavor::Fair(chan) => chan.disconnect(),
+            Flavor::Priority(chan) => chan.disconnect(),
+            Flavor::Delay(chan) => chan.disconnect(),
+            Flavor::Array(chan) => chan.disconnect(),
+            Flavor::Zero(chan) => chan.disconnect(),
+            Flavor::Timer(_) => false,
+        }
     }
 }
 
-impl<T> Clone for Sender<T> {
-    fn clone(&self) -> Self {
-        let mut inner = self.shared.inner.lock().unwrap();
-        inner.senders += 1;
-        drop(inner);
+impl<T> Handle for Sender<T> {
+    fn is_ready(&self) -> bool {
+        self.shared.flavor.can_send()
+    }
 
-        Sender {
-            shared: Arc::clone(&self.shared),
-        }
+    fn waiters(&self) -> Option<&Waiters> {
+        self.shared.flavor.senders()
     }
 }
 
-impl<T> Drop for Sender<T> {
-    fn drop(&mut self) {
-        let mut inner = self.shared.inner.lock().unwrap();
-        inner.senders -= 1;
-        let was_last = inner.senders == 0;
-        let receiver_alive = inner.receiver_alive;
-        drop(inner);
+impl<T> SelectSend for Sender<T> {}
 
-        // notifying receiver to stop blocking if this was the last receiver
-        if was_last && receiver_alive {
-            self.shared.can_receive.notify_one();
-        }
+impl<T> Handle for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        !self.buffer.is_empty() || self.shared.flavor.can_recv()
+    }
+
+    fn waiters(&self) -> Option<&Waiters> {
+        Some(self.shared.flavor.receivers())
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.shared.flavor.due()
     }
 }
 
-pub struct Receiver<T> {
-    shared: Arc<Shared<T>>,
-    buffer: VecDeque<T>,
+impl<T> SelectRecv for Receiver<T> {}
+
+/// Creates an unbounded mpsc channel
+pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    channel(Flavor::List(list::Channel::new()))
 }
 
-impl<T> Receiver<T> {
-    /// Returns `Some(value)` when value is available (will block if channel is empty) or `None` if channel is closed
-    pub fn receive(&mut self) -> Option<T> {
-        if let Some(value) = self.buffer.pop_front() {
-            return Some(value);
-        }
+/// Creates a bounded mpsc channel which holds at most `capacity` values
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero
+pub fn bounded_channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    channel(Flavor::Array(array::Channel::new(capacity)))
+}
 
-        let mut inner = self.shared.inner.lock().unwrap();
-        loop {
-            match inner.queue.pop_front() {
-                Some(value) => {
-                    if !inner.queue.is_empty() {
-                        std::mem::swap(&mut inner.queue, &mut self.buffer);
-                    }
-                    return Some(value);
-                }
-                None if inner.senders == 0 => return None,
-                None => {
-                    inner = self.shared.can_receive.wait(inner).unwrap();
-                }
-            }
-        }
-    }
+/// Creates an mpsc channel without a buffer: [`Sender::send`] blocks until
+/// the receiver takes the value
+///
+/// [`Sender::try_send`] succeeds only if the receiver is already blocked in
+/// [`Receiver::receive`] or awaiting [`Receiver::recv_async`], the value is handed to it.
+/// A receiver waiting in [`select!`] is woken up by blocked senders only
+///
+/// [`select!`]: crate::select!
+pub fn rendezvous_channel<T>() -> (Sender<T>, Receiver<T>) {
+    channel(Flavor::Zero(zero::Channel::new()))
 }
 
-impl<T> Drop for Receiver<T> {
-    fn drop(&mut self) {
-        let mut inner = self.shared.inner.lock().unwrap();
-        inner.receiver_alive = false;
-        drop(inner);
-    }
+/// Creates a receiver which gets the [`Instant`] it was due at once, after `delay` passes
+///
+/// There is no sender, the receiver is never disconnected
+pub fn after(delay: Duration) -> Receiver<Instant> {
+    receiver(Flavor::Timer(timer::Channel::after(delay)), 0)
 }
 
-struct Inner<T> {
-    queue: VecDeque<T>,
-    senders: usize,
-    receiver_alive: bool,
+/// Creates a receiver which gets the [`Instant`] each tick was scheduled at, every `period`
+///
+/// A slow receiver doesn't pile up missed ticks: at most one is pending at a time. The next
+/// tick is due a `period` after the previous one was, or a `period` after the previous one
+/// is received if that time has already passed
+pub fn tick(period: Duration) -> Receiver<Instant> {
+    receiver(Flavor::Timer(timer::Channel::tick(period)), 0)
 }
 
-struct Shared<T> {
-    inner: Mutex<Inner<T>>,
-    can_receive: Condvar,
+/// Creates a receiver which never gets anything, useful to disable an arm of [`select!`]
+///
+/// [`select!`]: crate::select!
+pub fn never<T>() -> Receiver<T> {
+    receiver(Flavor::Timer(timer::Channel::never()), 0)
 }
 
-/// Creates an unbounded mpsc channel
-pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
-    let inner = Inner {
-        queue: VecDeque::new(),
-        senders: 1,
-        receiver_alive: true,
-    };
-    let shared = Shared {
-        inner: Mutex::new(inner),
-        can_receive: Condvar::new(),
-    };
-    let shared = Arc::new(shared);
+/// Creates an unbounded mpsc channel where each [`Sender`] clone has its own queue
+///
+/// [`Receiver`] takes values from senders in turn, so a sender sending a lot can't delay
+/// the others' values behind its own. Values of one sender are still received in order
+pub fn fair_channel<T>() -> (Sender<T>, Receiver<T>) {
+    channel(Flavor::Fair(fair::Channel::new()))
+}
+
+/// Creates an unbounded mpsc channel delivering values with the highest priority first,
+/// see [`Sender::send_with_priority`]
+///
+/// Values of equal priority are received in the order they were sent
+pub fn priority_channel<T>() -> (Sender<T>, Receiver<T>) {
+    channel(Flavor::Priority(heap::Channel::new()))
+}
+
+/// Creates an unbounded mpsc channel delivering values once they are due, see
+/// [`DelaySender::send_after`]
+///
+/// Values sent with [`Sender::send`] are due right away. Values are received in the order
+/// they become due, [`Receiver::len`] counts the ones which aren't due yet too
+pub fn delay_channel<T>() -> (DelaySender<T>, Receiver<T>) {
+    let (sender, receiver) = channel(Flavor::Delay(heap::Channel::new()));
+    (DelaySender { sender }, receiver)
+}
+
+fn channel<T>(flavor: Flavor<T>) -> (Sender<T>, Receiver<T>) {
+    let receiver = receiver(flavor, 1);
     (
         Sender {
-            shared: Arc::clone(&shared),
-        },
-        Receiver {
-            shared: Arc::clone(&shared),
-            buffer: VecDeque::new(),
+            shared: Arc::clone(&receiver.shared),
+            id: receiver.shared.flavor.new_sender_id(),
+            #[cfg(feature = "async")]
+            sink: SinkState::default(),
         },
+        receiver,
     )
 }
 
+fn receiver<T>(flavor: Flavor<T>, senders: usize) -> Receiver<T> {
+    let shared = Shared {
+        senders: AtomicUsize::new(senders),
+        weak_senders: AtomicUsize::new(0),
+        receivers: AtomicUsize::new(1),
+        flavor,
+        buffered: AtomicUsize::new(0),
+        closed: Waiters::new(),
+    };
+    Receiver {
+        shared: Arc::new(shared),
+        buffer: VecDeque::new(),
+        #[cfg(feature = "async")]
+        oper: Operation::new(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "async")]
+    use std::task::Waker;
 
     #[test]
     fn it_works() {
-        let (mut tx, mut rx) = unbounded_channel();
+        let (tx, mut rx) = unbounded_channel();
         assert_eq!(tx.send(5), Ok(()));
-        assert_eq!(rx.receive(), Some(5));
+        assert_eq!(rx.receive(), Ok(5));
     }
 
     #[test]
     fn tx_closed() {
         let (tx, mut rx) = unbounded_channel::<()>();
         drop(tx);
-        assert_eq!(rx.receive(), None);
+        assert_eq!(rx.receive(), Err(RecvError));
     }
 
     #[test]
     fn rx_closed() {
-        let (mut tx, rx) = unbounded_channel();
+        let (tx, rx) = unbounded_channel();
         drop(rx);
-        assert_eq!(tx.send(5), Err(5));
+        assert_eq!(tx.send(5), Err(SendError(5)));
     }
 
     #[test]
     fn it_works_in_different_threads() {
         let (tx, mut rx) = unbounded_channel();
         {
-            let mut tx1 = tx.clone();
+            let tx1 = tx.clone();
             std::thread::spawn(move || {
                 assert_eq!(tx1.send(1), Ok(()));
             });
         }
         {
-            let mut tx2 = tx.clone();
+            let tx2 = tx.clone();
             std::thread::spawn(move || {
                 assert_eq!(tx2.send(1), Ok(()));
             });
         }
         drop(tx);
 
-        assert_eq!(rx.receive(), Some(1));
-        assert_eq!(rx.receive(), Some(1));
-        assert_eq!(rx.receive(), None);
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn iterators() {
+        let (tx, mut rx) = unbounded_channel();
+        for i in 0..3 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert_eq!(rx.receive(), Ok(0));
+        // rest of the claimed batch comes first
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(rx.try_iter().next(), None);
+
+        assert_eq!(tx.send(3), Ok(()));
+        let handle = std::thread::spawn(move || {
+            for i in 4..6 {
+                assert_eq!(tx.send(i), Ok(()));
+            }
+        });
+        assert_eq!(rx.iter().take(1).collect::<Vec<_>>(), [3]);
+        handle.join().unwrap();
+        assert_eq!(rx.into_iter().collect::<Vec<_>>(), [4, 5]);
+    }
+
+    #[test]
+    fn shared_sender() {
+        let (tx, rx) = unbounded_channel();
+        let tx = Arc::new(tx);
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let tx = Arc::clone(&tx);
+                std::thread::spawn(move || assert_eq!(tx.send(i), Ok(())))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        drop(tx);
+        let mut received: Vec<_> = rx.into_iter().collect();
+        received.sort();
+        assert_eq!(received, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn close() {
+        let (tx, mut rx) = bounded_channel(2);
+        assert!(!tx.is_closed());
+        assert_eq!(tx.send(1), Ok(()));
+        rx.close();
+        assert!(tx.is_closed());
+        assert!(rx.is_closed());
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Err(RecvError));
+
+        let (tx, rx) = unbounded_channel::<()>();
+        let handle = std::thread::spawn(move || tx.closed());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(rx);
+        handle.join().unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn closed_async() {
+        let (tx, rx) = rendezvous_channel::<()>();
+        let handle = std::thread::spawn(move || block_on(tx.closed_async()));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(rx);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn len() {
+        let (tx, mut rx) = unbounded_channel();
+        assert_eq!(tx.capacity(), None);
+        assert!(tx.is_empty());
+        for i in 0..40 {
+            assert_eq!(tx.send(i), Ok(()));
+        }
+        assert_eq!(tx.len(), 40);
+        // rest of the first block is claimed into the buffer
+        assert_eq!(rx.receive(), Ok(0));
+        assert_eq!(tx.len(), 39);
+        assert_eq!(rx.len(), 39);
+        assert_eq!(rx.try_iter().count(), 39);
+        assert!(tx.is_empty());
+
+        let (tx, mut rx) = bounded_channel(3);
+        assert_eq!(rx.capacity(), Some(3));
+        for i in 0..5 {
+            let _ = tx.try_send(i);
+            let _ = rx.try_receive();
+            assert_eq!(tx.try_send(i), Ok(()));
+        }
+        assert_eq!(tx.len(), 3);
+        let tx2 = tx.clone();
+        assert_eq!(rx.sender_count(), 2);
+        drop(tx2);
+        assert_eq!(tx.sender_count(), 1);
+
+        let (tx, _rx) = rendezvous_channel::<()>();
+        assert_eq!(tx.capacity(), Some(0));
+        assert_eq!(tx.len(), 0);
+    }
+
+    #[test]
+    fn pending_values_are_dropped_with_receiver() {
+        let value = Arc::new(());
+        let channels = || [unbounded_channel(), bounded_channel(64)];
+
+        // receiver first, with values both claimed into the buffer and left in the channel
+        for (tx, mut rx) in channels() {
+            for _ in 0..40 {
+                assert_eq!(tx.send(Arc::clone(&value)), Ok(()));
+            }
+            drop(rx.receive());
+            drop(rx);
+            assert_eq!(Arc::strong_count(&value), 1);
+            assert_eq!(tx.len(), 0);
+        }
+
+        // senders first, values wait for the receiver
+        for (tx, rx) in channels() {
+            for _ in 0..3 {
+                assert_eq!(tx.send(Arc::clone(&value)), Ok(()));
+            }
+            drop(tx);
+            assert_eq!(Arc::strong_count(&value), 4);
+            drop(rx);
+            assert_eq!(Arc::strong_count(&value), 1);
+        }
+
+        // closed receiver keeps values until it is dropped
+        for (tx, mut rx) in channels() {
+            for _ in 0..3 {
+                assert_eq!(tx.send(Arc::clone(&value)), Ok(()));
+            }
+            rx.close();
+            assert_eq!(Arc::strong_count(&value), 4);
+            drop(rx);
+            assert_eq!(Arc::strong_count(&value), 1);
+        }
+    }
+
+    #[test]
+    fn batches() {
+        for (tx, mut rx) in [unbounded_channel(), bounded_channel(8)] {
+            assert_eq!(tx.send_all(0..5), Ok(()));
+            let mut values = vec![-1];
+            assert_eq!(rx.receive_many(&mut values, 3), Ok(3));
+            assert_eq!(rx.receive_many(&mut values, 0), Ok(0));
+            assert_eq!(rx.receive_many(&mut values, 10), Ok(2));
+            assert_eq!(values, [-1, 0, 1, 2, 3, 4]);
+
+            let handle = std::thread::spawn(move || rx.receive_many(&mut Vec::new(), 10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            assert_eq!(tx.send_all([5]), Ok(()));
+            assert_eq!(handle.join().unwrap(), Ok(1));
+            assert_eq!(tx.send_all([6, 7]), Err(SendError(vec![6, 7])));
+        }
+    }
+
+    #[test]
+    fn bounded_blocks_when_full() {
+        let (tx, mut rx) = bounded_channel(1);
+        assert_eq!(tx.send(1), Ok(()));
+        let handle = std::thread::spawn(move || {
+            assert_eq!(tx.send(2), Ok(()));
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!handle.is_finished());
+
+        assert_eq!(rx.receive(), Ok(1));
+        handle.join().unwrap();
+        assert_eq!(rx.receive(), Ok(2));
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn bounded_rx_closed_while_blocked() {
+        let (tx, rx) = bounded_channel(1);
+        assert_eq!(tx.send(1), Ok(()));
+        let handle = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(rx);
+        assert_eq!(handle.join().unwrap(), Err(SendError(2)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn bounded_zero_capacity() {
+        let _ = bounded_channel::<()>(0);
+    }
+
+    #[test]
+    fn rendezvous_waits_for_receiver() {
+        let (tx, mut rx) = rendezvous_channel();
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        let handle = std::thread::spawn(move || {
+            assert_eq!(tx.send(2), Ok(()));
+            assert_eq!(tx.send(3), Ok(()));
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!handle.is_finished());
+
+        assert_eq!(rx.receive(), Ok(2));
+        assert_eq!(rx.receive(), Ok(3));
+        handle.join().unwrap();
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn rendezvous_rx_closed_while_blocked() {
+        let (tx, rx) = rendezvous_channel();
+        let handle = std::thread::spawn(move || tx.send(1));
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(rx);
+        assert_eq!(handle.join().unwrap(), Err(SendError(1)));
+    }
+
+    #[test]
+    fn try_receive() {
+        let (tx, mut rx) = unbounded_channel();
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(tx.send(2), Ok(()));
+        assert_eq!(rx.try_receive(), Ok(1));
+        drop(tx);
+        assert_eq!(rx.try_receive(), Ok(2));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn try_send() {
+        let (tx, mut rx) = bounded_channel(1);
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(tx.try_send(3), Ok(()));
+        drop(rx);
+        assert_eq!(tx.try_send(4), Err(TrySendError::Disconnected(4)));
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let (tx, mut rx) = unbounded_channel();
+        assert_eq!(
+            rx.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx.send(1), Ok(()));
+        });
+        assert_eq!(rx.receive_timeout(Duration::from_secs(10)), Ok(1));
+        assert_eq!(
+            rx.receive_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn receive_deadline() {
+        let (_tx, mut rx) = unbounded_channel::<()>();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(
+            rx.receive_deadline(deadline),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(Instant::now() >= deadline);
+        assert_eq!(
+            rx.receive_deadline(Instant::now() - Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[cfg(feature = "async")]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        use std::task::Wake;
+
+        struct ThreadWaker(std::thread::Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn recv_async() {
+        let (tx, mut rx) = unbounded_channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx.send(1), Ok(()));
+        });
+        assert_eq!(block_on(rx.recv_async()), Ok(1));
+        assert_eq!(block_on(rx.recv_async()), Err(RecvError));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn recv_async_cancelled() {
+        let (tx, mut rx) = rendezvous_channel::<i32>();
+        let (waker, _) = flag_waker();
+        let mut future = rx.recv_async();
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert!(tx.shared.flavor.can_send());
+        drop(future);
+        // dropped future no longer counts as a waiting receiver
+        assert!(!tx.shared.flavor.can_send());
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(tx.len(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn rendezvous_value_handed_to_cancelled_receiver() {
+        let value = Arc::new(());
+        let (tx, mut rx) = rendezvous_channel();
+        let (waker, woken) = flag_waker();
+        let mut future = rx.recv_async();
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(tx.try_send(Arc::clone(&value)), Ok(()));
+        assert!(woken.load(std::sync::atomic::Ordering::SeqCst));
+        drop(future);
+
+        // the value stays in the channel until received or dropped with the receiver
+        assert_eq!(tx.len(), 1);
+        assert!(rx.try_receive().is_ok());
+        assert_eq!(
+            tx.try_send(Arc::clone(&value)),
+            Err(TrySendError::Full(Arc::clone(&value)))
+        );
+
+        let mut future = rx.recv_async();
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(tx.try_send(Arc::clone(&value)), Ok(()));
+        drop(future);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn stream() {
+        use futures_core::Stream;
+
+        let (tx, mut rx) = bounded_channel(1);
+        std::thread::spawn(move || {
+            for i in 0..3 {
+                assert_eq!(tx.send(i), Ok(()));
+            }
+        });
+        let mut received = Vec::new();
+        while let Some(value) = block_on(std::future::poll_fn(|cx| Pin::new(&mut rx).poll_next(cx)))
+        {
+            received.push(value);
+        }
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+
+    #[cfg(feature = "async")]
+    fn flag_waker() -> (Waker, Arc<std::sync::atomic::AtomicBool>) {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            task::Wake,
+        };
+
+        struct FlagWaker(Arc<AtomicBool>);
+
+        impl Wake for FlagWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let flag = Arc::new(AtomicBool::new(false));
+        (Waker::from(Arc::new(FlagWaker(Arc::clone(&flag)))), flag)
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn after_async_wakes_latest_poll() {
+        use std::sync::atomic::Ordering;
+
+        let mut rx = after(Duration::from_millis(20));
+        let (first, first_woken) = flag_waker();
+        let (second, second_woken) = flag_waker();
+        let mut future = rx.recv_async();
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&first))
+            .is_pending());
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&second))
+            .is_pending());
+        std::thread::sleep(Duration::from_millis(60));
+        // the second poll replaced the wakeup of the first one
+        assert!(!first_woken.load(Ordering::SeqCst));
+        assert!(second_woken.load(Ordering::SeqCst));
+        assert!(Pin::new(&mut future)
+            .poll(&mut Context::from_waker(&second))
+            .is_ready());
+    }
+
+    #[cfg(all(feature = "async", target_os = "linux"))]
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn async_timers_share_a_thread() {
+        fn threads() -> usize {
+            std::fs::read_dir("/proc/self/task").unwrap().count()
+        }
+
+        let (waker, _) = flag_waker();
+        let mut cx = Context::from_waker(&waker);
+        let before = threads();
+        let (mut receivers, mut delayed) = (Vec::new(), Vec::new());
+        for _ in 0..200 {
+            let mut rx = after(Duration::from_secs(30));
+            assert!(Pin::new(&mut rx.recv_async()).poll(&mut cx).is_pending());
+            receivers.push(rx);
+        }
+        for _ in 0..200 {
+            let (tx, mut rx) = delay_channel();
+            assert_eq!(tx.send_after(1, Duration::from_secs(30)), Ok(()));
+            assert!(Pin::new(&mut rx.recv_async()).poll(&mut cx).is_pending());
+            delayed.push((tx, rx));
+        }
+        // other tests running meanwhile may start a few threads of their own
+        assert!(threads() < before + 50);
+    }
+
+    #[test]
+    fn priority_channel_highest_first() {
+        let (tx, mut rx) = priority_channel();
+        assert_eq!(tx.send("low 1"), Ok(()));
+        assert_eq!(tx.send_with_priority("high 1", 5), Ok(()));
+        assert_eq!(tx.send_with_priority("mid", 2), Ok(()));
+        assert_eq!(tx.try_send("low 2"), Ok(()));
+        assert_eq!(tx.send_with_priority("high 2", 5), Ok(()));
+        assert_eq!(tx.len(), 5);
+        drop(tx);
+
+        let received: Vec<_> = rx.iter().collect();
+        assert_eq!(received, ["high 1", "high 2", "mid", "low 1", "low 2"]);
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn priority_channel_close() {
+        let value = Arc::new(());
+        let (tx, mut rx) = priority_channel();
+        assert_eq!(
+            rx.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert_eq!(tx.send_with_priority(Arc::clone(&value), 1), Ok(()));
+        rx.close();
+        assert!(tx.is_closed());
+        assert_eq!(
+            tx.send(Arc::clone(&value)),
+            Err(SendError(Arc::clone(&value)))
+        );
+        assert_eq!(rx.len(), 1);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn delay_channel_values_arrive_when_due() {
+        let (tx, mut rx) = delay_channel();
+        let start = Instant::now();
+        assert_eq!(tx.send_after("late", Duration::from_millis(40)), Ok(()));
+        assert_eq!(tx.send_after("soon", Duration::from_millis(20)), Ok(()));
+        assert_eq!(tx.send("now"), Ok(()));
+        drop(tx);
+
+        assert_eq!(rx.try_receive(), Ok("now"));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(rx.receive(), Ok("soon"));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(rx.receive(), Ok("late"));
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn delay_channel_earlier_value_wakes_up_receiver() {
+        let (tx, mut rx) = delay_channel();
+        assert_eq!(tx.send_after(2, Duration::from_secs(60)), Ok(()));
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx.send_at(1, Instant::now()), Ok(()));
+        });
+        assert_eq!(rx.receive_timeout(Duration::from_secs(10)), Ok(1));
+        assert_eq!(
+            rx.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert_eq!(rx.len(), 1);
+    }
+
+    #[test]
+    fn delay_channel_never_due() {
+        let (tx, mut rx) = delay_channel();
+        assert_eq!(tx.send_after(1, Duration::MAX), Ok(()));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(rx.len(), 1);
+        // nothing will ever be received once senders are gone
+        drop(tx);
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn delay_channel_close() {
+        let value = Arc::new(());
+        let (tx, mut rx) = delay_channel();
+        assert_eq!(
+            tx.send_after(Arc::clone(&value), Duration::from_secs(60)),
+            Ok(())
+        );
+        rx.close();
+        assert!(tx.is_closed());
+        assert_eq!(
+            tx.send(Arc::clone(&value)),
+            Err(SendError(Arc::clone(&value)))
+        );
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn delay_channel_recv_async() {
+        let (tx, mut rx) = delay_channel();
+        let start = Instant::now();
+        assert_eq!(tx.send_after(1, Duration::from_millis(20)), Ok(()));
+        assert_eq!(block_on(rx.recv_async()), Ok(1));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn fair_channel_takes_turns() {
+        let (chatty, mut rx) = fair_channel();
+        let quiet = chatty.clone();
+        assert_eq!(chatty.send_all(["a1", "a2", "a3"]), Ok(()));
+        assert_eq!(quiet.send("b1"), Ok(()));
+        assert_eq!(chatty.downgrade().upgrade().unwrap().send("c1"), Ok(()));
+        assert_eq!(quiet.send("b2"), Ok(()));
+        assert_eq!(rx.len(), 6);
+        drop((chatty, quiet));
+
+        let received: Vec<_> = rx.iter().collect();
+        assert_eq!(received, ["a1", "b1", "c1", "a2", "b2", "a3"]);
+    }
+
+    #[test]
+    fn weak_sender() {
+        let (tx, mut rx) = unbounded_channel();
+        let weak = tx.downgrade();
+        assert_eq!(tx.weak_sender_count(), 1);
+        assert_eq!(weak.clone().sender_count(), 1);
+        assert_eq!(weak.weak_sender_count(), 1);
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(upgraded.send(1), Ok(()));
+        drop(upgraded);
+        drop(tx);
+        assert_eq!(rx.receive(), Ok(1));
+        // only a weak sender is left, so the channel is closed
+        assert_eq!(rx.receive(), Err(RecvError));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn timers() {
+        let start = Instant::now();
+        let mut timer = after(Duration::from_millis(10));
+        assert_eq!(timer.try_receive(), Err(TryRecvError::Empty));
+        assert!(timer.receive().unwrap() >= start + Duration::from_millis(10));
+        assert_eq!(
+            timer.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let mut ticker = tick(Duration::from_millis(10));
+        let ticks: Vec<_> = ticker.iter().take(3).collect();
+        assert!(ticks.windows(2).all(|pair| pair[1] > pair[0]));
+
+        let mut never = never::<i32>();
+        assert_eq!(
+            never.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(!never.is_closed());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_async_waits_for_free_slot() {
+        let (tx, mut rx) = bounded_channel(1);
+        assert_eq!(block_on(tx.send_async(1)), Ok(()));
+        let handle = std::thread::spawn(move || {
+            assert_eq!(block_on(tx.send_async(2)), Ok(()));
+            assert_eq!(block_on(tx.send_async(3)), Ok(()));
+        });
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Ok(2));
+        assert_eq!(rx.receive(), Ok(3));
+        handle.join().unwrap();
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_async_cancelled_passes_wakeup_on() {
+        use std::sync::atomic::Ordering;
+
+        let (tx1, mut rx) = bounded_channel(1);
+        let tx2 = tx1.clone();
+        assert_eq!(tx1.send(0), Ok(()));
+
+        let (waker1, woken1) = flag_waker();
+        let (waker2, woken2) = flag_waker();
+        let mut first = tx1.send_async(1);
+        let mut second = tx2.send_async(2);
+        let mut cx1 = Context::from_waker(&waker1);
+        let mut cx2 = Context::from_waker(&waker2);
+        assert!(Pin::new(&mut first).poll(&mut cx1).is_pending());
+        assert!(Pin::new(&mut second).poll(&mut cx2).is_pending());
+
+        assert_eq!(rx.receive(), Ok(0));
+        assert!(woken1.load(Ordering::SeqCst));
+        assert!(!woken2.load(Ordering::SeqCst));
+
+        drop(first);
+        assert!(woken2.load(Ordering::SeqCst));
+        assert_eq!(Pin::new(&mut second).poll(&mut cx2), Poll::Ready(Ok(())));
+        drop(second);
+        assert_eq!(rx.receive(), Ok(2));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn send_async_rx_closed() {
+        let (tx, rx) = bounded_channel(1);
+        assert_eq!(tx.send(1), Ok(()));
+        let (waker, woken) = flag_waker();
+        let mut future = tx.send_async(2);
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        drop(rx);
+        assert!(woken.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(
+            Pin::new(&mut future).poll(&mut cx),
+            Poll::Ready(Err(SendError(2)))
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn rendezvous_send_async() {
+        let (tx, mut rx) = rendezvous_channel();
+        let handle = std::thread::spawn(move || {
+            assert_eq!(block_on(tx.send_async(1)), Ok(()));
+            assert_eq!(block_on(tx.send_async(2)), Ok(()));
+        });
+        assert_eq!(block_on(rx.recv_async()), Ok(1));
+        assert_eq!(rx.receive(), Ok(2));
+        handle.join().unwrap();
+        assert_eq!(block_on(rx.recv_async()), Err(RecvError));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn sink() {
+        use futures_sink::Sink;
+
+        let (mut tx, mut rx) = bounded_channel(1);
+        let handle = std::thread::spawn(move || {
+            for i in 0..3 {
+                block_on(std::future::poll_fn(|cx| Pin::new(&mut tx).poll_ready(cx))).unwrap();
+                Pin::new(&mut tx).start_send(i).unwrap();
+            }
+            block_on(std::future::poll_fn(|cx| Pin::new(&mut tx).poll_flush(cx))).unwrap();
+        });
+        assert_eq!(rx.receive(), Ok(0));
+        assert_eq!(rx.receive(), Ok(1));
+        assert_eq!(rx.receive(), Ok(2));
+        handle.join().unwrap();
+        assert_eq!(rx.receive(), Err(RecvError));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn sink_waits_while_full() {
+        use futures_sink::Sink;
+
+        let (mut tx, mut rx) = bounded_channel(1);
+        let (waker, woken) = flag_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(Pin::new(&mut tx).poll_ready(&mut cx), Poll::Ready(Ok(())));
+        Pin::new(&mut tx).start_send(1).unwrap();
+        assert!(Pin::new(&mut tx).poll_ready(&mut cx).is_pending());
+        assert_eq!(tx.len(), 1);
+
+        assert_eq!(rx.receive(), Ok(1));
+        assert!(woken.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(Pin::new(&mut tx).poll_ready(&mut cx), Poll::Ready(Ok(())));
+        Pin::new(&mut tx).start_send(2).unwrap();
+        assert_eq!(rx.receive(), Ok(2));
     }
 }
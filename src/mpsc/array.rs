@@ -0,0 +1,401 @@
+//! Lock-free bounded queue, a ring buffer of slots with sequence stamps (Vyukov's design,
+//! following crossbeam's array flavor).
+//!
+//! `head` and `tail` hold a lap number in the upper bits and a slot index in the lower bits.
+//! A slot is ready for writing when its stamp equals `tail` and ready for reading when
+//! its stamp equals `head + 1`.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{self, AtomicUsize, Ordering},
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{
+    error::RecvError,
+    waker::{Operation, Waiter},
+};
+use crate::{
+    error::{RecvTimeoutError, SendError, TryRecvError, TrySendError},
+    utils::{Backoff, CachePadded},
+    waker::Waiters,
+};
+
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub(crate) struct Channel<T> {
+    head: CachePadded<AtomicUsize>,
+    /// has `mark_bit` set once channel is disconnected
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[Slot<T>]>,
+    capacity: usize,
+    /// smallest power of two above `capacity`, bits below it hold the slot index
+    mark_bit: usize,
+    one_lap: usize,
+    /// senders blocked on a full channel
+    pub(crate) senders: Waiters,
+    /// receivers blocked on an empty channel
+    pub(crate) receivers: Waiters,
+}
+
+// values are only ever moved between threads, never shared
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+
+        let mark_bit = (capacity + 1).next_power_of_two();
+        let buffer = (0..capacity)
+            .map(|i| Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Channel {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            buffer,
+            capacity,
+            mark_bit,
+            one_lap: mark_bit * 2,
+            senders: Waiters::new(),
+            receivers: Waiters::new(),
+        }
+    }
+
+    /// Index right after `index`, wrapping around to the next lap
+    fn next_index(&self, index: usize) -> usize {
+        if (index & (self.mark_bit - 1)) + 1 < self.capacity {
+            index + 1
+        } else {
+            (index & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    pub(crate) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let backoff = Backoff::new();
+        let mut tail = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            if tail & self.mark_bit != 0 {
+                return Err(TrySendError::Disconnected(value));
+            }
+
+            let slot = &self.buffer[tail & (self.mark_bit - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if tail == stamp {
+                // slot is free, try to claim it
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    self.next_index(tail),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { slot.value.get().write(MaybeUninit::new(value)) };
+                        slot.stamp.store(tail + 1, Ordering::Release);
+                        self.receivers.notify();
+                        return Ok(());
+                    }
+                    Err(current) => {
+                        tail = current;
+                        backoff.spin();
+                    }
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // slot still holds a value from the previous lap
+                atomic::fence(Ordering::SeqCst);
+                let head = self.head.load(Ordering::Relaxed);
+                if head.wrapping_add(self.one_lap) == tail {
+                    return Err(TrySendError::Full(value));
+                }
+                backoff.spin();
+                tail = self.tail.load(Ordering::Relaxed);
+            } else {
+                // another sender claimed the slot but `tail` wasn't reloaded yet
+                backoff.snooze();
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Blocks while the channel is full
+    pub(crate) fn send(&self, mut value: T) -> Result<(), SendError<T>> {
+        loop {
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(value)) => return Err(SendError(value)),
+                Err(TrySendError::Full(rejected)) => value = rejected,
+            }
+            self.senders
+                .wait(None, || !self.is_full() || self.is_disconnected());
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let backoff = Backoff::new();
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.buffer[head & (self.mark_bit - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if head + 1 == stamp {
+                // slot holds a value, try to claim it
+                match self.head.compare_exchange_weak(
+                    head,
+                    self.next_index(head),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { slot.value.get().read().assume_init() };
+                        slot.stamp
+                            .store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        self.senders.notify();
+                        return Ok(value);
+                    }
+                    Err(current) => {
+                        head = current;
+                        backoff.spin();
+                    }
+                }
+            } else if stamp == head {
+                // slot is waiting for a value of this lap
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail & !self.mark_bit == head {
+                    return Err(match tail & self.mark_bit {
+                        0 => TryRecvError::Empty,
+                        _ => TryRecvError::Disconnected,
+                    });
+                }
+                backoff.spin();
+                head = self.head.load(Ordering::Relaxed);
+            } else {
+                // another receiver claimed the slot but `head` wasn't reloaded yet
+                backoff.snooze();
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            self.receivers
+                .wait(deadline, || !self.is_empty() || self.is_disconnected());
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head = self.head.load(Ordering::SeqCst);
+
+            // `head` is consistent only if `tail` didn't move in between
+            if self.tail.load(Ordering::SeqCst) == tail {
+                let head_index = head & (self.mark_bit - 1);
+                let tail_index = tail & (self.mark_bit - 1);
+                return if head_index < tail_index {
+                    tail_index - head_index
+                } else if head_index > tail_index {
+                    self.capacity - head_index + tail_index
+                } else if tail & !self.mark_bit == head {
+                    0
+                } else {
+                    self.capacity
+                };
+            }
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        let head = self.head.load(Ordering::SeqCst);
+        let tail = self.tail.load(Ordering::SeqCst);
+        tail & !self.mark_bit == head
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        let tail = self.tail.load(Ordering::SeqCst);
+        let head = self.head.load(Ordering::SeqCst);
+        head.wrapping_add(self.one_lap) == tail & !self.mark_bit
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.tail.load(Ordering::SeqCst) & self.mark_bit != 0
+    }
+
+    /// Stops accepting new values and wakes up everyone blocked.
+    /// Returns `false` if channel was already disconnected
+    pub(crate) fn disconnect(&self) -> bool {
+        let tail = self.tail.fetch_or(self.mark_bit, Ordering::SeqCst);
+        if tail & self.mark_bit == 0 {
+            self.senders.notify_all();
+            self.receivers.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Channel<T> {
+    pub(crate) fn poll_recv(
+        &self,
+        oper: Operation,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, RecvError>> {
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            result => return Poll::Ready(result.map_err(|_| RecvError)),
+        }
+        self.receivers
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => Poll::Pending,
+            result => {
+                self.receivers.unregister(oper);
+                Poll::Ready(result.map_err(|_| RecvError))
+            }
+        }
+    }
+
+    /// Pushes `value` once there's a free slot. `queued` tells whether `oper` was registered
+    /// among blocked senders by a previous poll
+    pub(crate) fn poll_send(
+        &self,
+        value: &mut Option<T>,
+        oper: Operation,
+        queued: &mut bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), SendError<T>>> {
+        self.senders.poll(oper, queued, cx, || {
+            let taken = value.take().expect("value sent twice");
+            match self.try_send(taken) {
+                Ok(()) => Some(Ok(())),
+                Err(TrySendError::Disconnected(taken)) => Some(Err(SendError(taken))),
+                Err(TrySendError::Full(taken)) => {
+                    *value = Some(taken);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Gives up the turn of `oper` among blocked senders without losing a wakeup
+    pub(crate) fn cancel_send(&self, oper: Operation, queued: &mut bool) {
+        self.senders.cancel(oper, queued);
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let head_index = *self.head.get_mut() & (self.mark_bit - 1);
+        for i in 0..self.len() {
+            let index = (head_index + i) % self.capacity;
+            unsafe { (*self.buffer[index].value.get()).assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn wraps_around() {
+        let chan = Channel::new(3);
+        for lap in 0..5 {
+            for i in 0..3 {
+                chan.try_send(lap * 3 + i).unwrap();
+            }
+            assert_eq!(chan.len(), 3);
+            assert_eq!(chan.try_send(0), Err(TrySendError::Full(0)));
+            for i in 0..3 {
+                assert_eq!(chan.try_recv(), Ok(lap * 3 + i));
+            }
+            assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn many_producers_and_consumers() {
+        const THREADS: usize = if cfg!(miri) { 2 } else { 8 };
+        const VALUES: usize = if cfg!(miri) { 50 } else { 10_000 };
+
+        let chan = Arc::new(Channel::new(4));
+        let sum = Arc::new(AtomicUsize::new(0));
+        let producers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let chan = Arc::clone(&chan);
+                std::thread::spawn(move || {
+                    for i in 0..VALUES {
+                        chan.send(i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let chan = Arc::clone(&chan);
+                let sum = Arc::clone(&sum);
+                std::thread::spawn(move || {
+                    while let Ok(value) = chan.recv(None) {
+                        sum.fetch_add(value, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        chan.disconnect();
+        for consumer in consumers {
+            consumer.join().unwrap();
+        }
+        assert_eq!(
+            sum.load(Ordering::Relaxed),
+            THREADS * VALUES * (VALUES - 1) / 2
+        );
+    }
+
+    #[test]
+    fn unreceived_values_are_dropped() {
+        let value = Arc::new(());
+        let chan = Channel::new(4);
+        for _ in 0..3 {
+            chan.try_send(Arc::clone(&value)).unwrap();
+            drop(chan.try_recv());
+            chan.try_send(Arc::clone(&value)).unwrap();
+        }
+        drop(chan.try_recv());
+        drop(chan);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
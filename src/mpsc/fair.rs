@@ -0,0 +1,208 @@
+//! Unbounded queue with a separate FIFO per sender, served round-robin.
+//!
+//! Every [`Sender`](super::Sender) gets its own id, values are queued under it behind a
+//! mutex. `ready` lists the ids with queued values in the order they are served: a sender
+//! goes to the back after each received value, so a busy one can't starve the others.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{error::RecvError, waker::Operation, waker::Waiter};
+use crate::{
+    error::{RecvTimeoutError, SendError, TryRecvError},
+    waker::Waiters,
+};
+
+struct Inner<T> {
+    /// values of senders which have something queued, by sender id
+    queues: HashMap<usize, VecDeque<T>>,
+    /// ids of `queues`, the front one is received from next
+    ready: VecDeque<usize>,
+    len: usize,
+    is_disconnected: bool,
+}
+
+pub(crate) struct Channel<T> {
+    inner: Mutex<Inner<T>>,
+    next_id: AtomicUsize,
+    /// receivers blocked on an empty channel
+    pub(crate) receivers: Waiters,
+}
+
+impl<T> Channel<T> {
+    pub(crate) fn new() -> Self {
+        Channel {
+            inner: Mutex::new(Inner {
+                queues: HashMap::new(),
+                ready: VecDeque::new(),
+                len: 0,
+                is_disconnected: false,
+            }),
+            next_id: AtomicUsize::new(0),
+            receivers: Waiters::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Id for a new sender, values sent under different ids take turns
+    pub(crate) fn new_sender_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn send(&self, id: usize, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return Err(SendError(value));
+        }
+        let queue = inner.queues.entry(id).or_default();
+        queue.push_back(value);
+        if queue.len() == 1 {
+            inner.ready.push_back(id);
+        }
+        inner.len += 1;
+        drop(inner);
+
+        self.receivers.notify();
+        Ok(())
+    }
+
+    /// Takes the oldest value of the sender whose turn it is
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut inner = self.lock();
+        let Some(id) = inner.ready.pop_front() else {
+            return Err(match inner.is_disconnected {
+                true => TryRecvError::Disconnected,
+                false => TryRecvError::Empty,
+            });
+        };
+        let queue = inner.queues.get_mut(&id).expect("ready sender has a queue");
+        let value = queue.pop_front().expect("ready sender has a value");
+        // dropped senders don't leave empty queues behind
+        if queue.is_empty() {
+            inner.queues.remove(&id);
+        } else {
+            inner.ready.push_back(id);
+        }
+        inner.len -= 1;
+        Ok(value)
+    }
+
+    /// Blocking version of [`Channel::try_recv`]
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            self.receivers
+                .wait(deadline, || !self.is_empty() || self.is_disconnected());
+        }
+    }
+
+    /// Async version of [`Channel::try_recv`], registers the task as `oper` while channel is empty
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_recv(
+        &self,
+        oper: Operation,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, RecvError>> {
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            result => return Poll::Ready(result.map_err(|_| RecvError)),
+        }
+        self.receivers
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => Poll::Pending,
+            result => {
+                self.receivers.unregister(oper);
+                Poll::Ready(result.map_err(|_| RecvError))
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lock().len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.lock().is_disconnected
+    }
+
+    /// Drops values left in the channel
+    pub(crate) fn drain(&self) {
+        let mut inner = self.lock();
+        let queues = std::mem::take(&mut inner.queues);
+        inner.ready.clear();
+        inner.len = 0;
+        drop(inner);
+
+        // values may run arbitrary code in `Drop`, so they are dropped outside the lock
+        drop(queues);
+    }
+
+    /// Stops accepting new values and wakes up blocked receivers.
+    /// Returns `false` if channel was already disconnected
+    pub(crate) fn disconnect(&self) -> bool {
+        let was_disconnected = std::mem::replace(&mut self.lock().is_disconnected, true);
+        if !was_disconnected {
+            self.receivers.notify_all();
+        }
+        !was_disconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn senders_take_turns() {
+        let chan = Channel::new();
+        let (busy, quiet) = (chan.new_sender_id(), chan.new_sender_id());
+        for i in 0..3 {
+            assert_eq!(chan.send(busy, i), Ok(()));
+        }
+        assert_eq!(chan.send(quiet, 10), Ok(()));
+        assert_eq!(chan.send(quiet, 11), Ok(()));
+        assert_eq!(chan.len(), 5);
+
+        let received: Vec<_> = std::iter::from_fn(|| chan.try_recv().ok()).collect();
+        assert_eq!(received, [0, 10, 1, 11, 2]);
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+        assert!(chan.lock().queues.is_empty());
+    }
+
+    #[test]
+    fn disconnect() {
+        let chan = Channel::new();
+        let id = chan.new_sender_id();
+        assert_eq!(chan.send(id, 1), Ok(()));
+        assert!(chan.disconnect());
+        assert!(!chan.disconnect());
+        assert_eq!(chan.send(id, 2), Err(SendError(2)));
+        assert_eq!(chan.recv(None), Ok(1));
+        assert_eq!(chan.recv(None), Err(RecvTimeoutError::Disconnected));
+    }
+}
@@ -0,0 +1,321 @@
+//! Unbounded queue ordered by a key, a `BinaryHeap` behind a mutex.
+//!
+//! The value with the greatest key is received first. Each value gets a sequence number
+//! when sent, so values with equal keys are received in the order they were sent.
+//!
+//! A key may also hold its value back until some point in time, see [`Due`]. Receivers then
+//! sleep until the top value is due, senders wake them up to recheck whenever something is
+//! sent, since the new value may be due sooner.
+
+use std::{cmp::Ordering as CmpOrdering, collections::BinaryHeap, sync::Mutex, time::Instant};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{
+    error::RecvError,
+    waker::{Alarm, Operation, Waiter},
+};
+use crate::{
+    error::{RecvTimeoutError, SendError, TryRecvError},
+    waker::Waiters,
+};
+
+/// Ordering of values in a [`Channel`]
+pub(crate) trait Key: Ord {
+    /// Whether a value with this key can be received at `now`
+    fn is_due(&self, _now: Instant) -> bool {
+        true
+    }
+
+    /// When a value which isn't due yet will be, `None` if it never will
+    fn due(&self) -> Option<Instant> {
+        None
+    }
+}
+
+impl Key for u32 {}
+
+/// Key of a value which can't be received before the given time, or ever if it's `None`.
+/// Earlier ones are greater, so they are received first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Due(pub(crate) Option<Instant>);
+
+impl Due {
+    pub(crate) fn now() -> Due {
+        Due(Some(Instant::now()))
+    }
+}
+
+impl Ord for Due {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        match (self.0, other.0) {
+            (Some(due), Some(other)) => other.cmp(&due),
+            (due, other) => due.is_some().cmp(&other.is_some()),
+        }
+    }
+}
+
+impl PartialOrd for Due {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Key for Due {
+    fn is_due(&self, now: Instant) -> bool {
+        self.0.is_some_and(|due| due <= now)
+    }
+
+    fn due(&self) -> Option<Instant> {
+        self.0
+    }
+}
+
+struct Entry<K, T> {
+    key: K,
+    /// order of sending, lower goes first among equal keys
+    seq: u64,
+    value: T,
+}
+
+impl<K: Ord, T> Ord for Entry<K, T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<K: Ord, T> PartialOrd for Entry<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> PartialEq for Entry<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<K: Ord, T> Eq for Entry<K, T> {}
+
+struct Inner<K, T> {
+    heap: BinaryHeap<Entry<K, T>>,
+    next_seq: u64,
+    is_disconnected: bool,
+}
+
+impl<K: Key, T> Inner<K, T> {
+    /// Whether the top value can be taken at `now`
+    fn check(&self, now: Instant) -> Result<(), TryRecvError> {
+        match self.heap.peek() {
+            Some(entry) if entry.key.is_due(now) => Ok(()),
+            // values which are never due don't keep a disconnected channel open
+            Some(entry) if entry.key.due().is_some() => Err(TryRecvError::Empty),
+            _ if self.is_disconnected => Err(TryRecvError::Disconnected),
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.heap.peek().and_then(|entry| entry.key.due())
+    }
+}
+
+pub(crate) struct Channel<K, T> {
+    inner: Mutex<Inner<K, T>>,
+    /// receivers blocked until the top value is due
+    pub(crate) receivers: Waiters,
+    /// wakes up a task once the top value is due, as no sender will
+    #[cfg(feature = "async")]
+    alarm: Alarm,
+}
+
+impl<K: Key, T> Channel<K, T> {
+    pub(crate) fn new() -> Self {
+        Channel {
+            inner: Mutex::new(Inner {
+                heap: BinaryHeap::new(),
+                next_seq: 0,
+                is_disconnected: false,
+            }),
+            receivers: Waiters::new(),
+            #[cfg(feature = "async")]
+            alarm: Alarm::new(),
+        }
+    }
+
+    pub(crate) fn send(&self, key: K, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_disconnected {
+            return Err(SendError(value));
+        }
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.heap.push(Entry { key, seq, value });
+        drop(inner);
+
+        self.receivers.notify();
+        Ok(())
+    }
+
+    /// Takes the value with the greatest key if it is due
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.check(Instant::now())?;
+        Ok(inner.heap.pop().unwrap().value)
+    }
+
+    /// Blocking version of [`Channel::try_recv`]
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.check(Instant::now()) {
+                Ok(()) => return Ok(inner.heap.pop().unwrap().value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let next_due = inner.next_due();
+            let seen_seq = inner.next_seq;
+            let was_disconnected = inner.is_disconnected;
+            drop(inner);
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            let wake_at = match (deadline, next_due) {
+                (Some(deadline), Some(due)) => Some(deadline.min(due)),
+                (deadline, due) => deadline.or(due),
+            };
+            self.receivers.wait(wake_at, || {
+                let inner = self.inner.lock().unwrap();
+                inner.next_seq != seen_seq || inner.is_disconnected != was_disconnected
+            });
+        }
+    }
+
+    /// Async version of [`Channel::try_recv`], registers the task as `oper` while nothing is due
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_recv(
+        &self,
+        oper: Operation,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, RecvError>> {
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            result => return Poll::Ready(result.map_err(|_| RecvError)),
+        }
+        self.receivers
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {
+                if let Some(due) = self.next_due() {
+                    self.alarm.wake_at(due, cx.waker());
+                }
+                Poll::Pending
+            }
+            result => {
+                self.receivers.unregister(oper);
+                Poll::Ready(result.map_err(|_| RecvError))
+            }
+        }
+    }
+
+    /// Whether [`Channel::try_recv`] wouldn't return `Empty`
+    pub(crate) fn can_recv(&self) -> bool {
+        self.inner.lock().unwrap().check(Instant::now()) != Err(TryRecvError::Empty)
+    }
+
+    /// When the top value will be due, if it isn't yet
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.inner.lock().unwrap().next_due()
+    }
+
+    /// Number of values not received yet, including the ones which aren't due
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().unwrap().heap.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.inner.lock().unwrap().is_disconnected
+    }
+
+    /// Drops values left in the channel
+    pub(crate) fn drain(&self) {
+        let heap = std::mem::take(&mut self.inner.lock().unwrap().heap);
+
+        // values may run arbitrary code in `Drop`, so they are dropped outside the lock
+        drop(heap);
+    }
+
+    /// Stops accepting new values and wakes up blocked receivers.
+    /// Returns `false` if channel was already disconnected
+    pub(crate) fn disconnect(&self) -> bool {
+        let was_disconnected =
+            std::mem::replace(&mut self.inner.lock().unwrap().is_disconnected, true);
+        if !was_disconnected {
+            self.receivers.notify_all();
+        }
+        !was_disconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn greatest_key_first() {
+        let chan = Channel::new();
+        assert_eq!(chan.send(0, "low 1"), Ok(()));
+        assert_eq!(chan.send(5, "high 1"), Ok(()));
+        assert_eq!(chan.send(2, "mid"), Ok(()));
+        assert_eq!(chan.send(0, "low 2"), Ok(()));
+        assert_eq!(chan.send(5, "high 2"), Ok(()));
+        assert!(chan.disconnect());
+
+        let received: Vec<_> = std::iter::from_fn(|| chan.recv(None).ok()).collect();
+        assert_eq!(received, ["high 1", "high 2", "mid", "low 1", "low 2"]);
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn earliest_due_first() {
+        let chan = Channel::new();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+        assert_eq!(chan.send(Due(None), "never"), Ok(()));
+        assert_eq!(chan.send(Due(Some(later)), "later"), Ok(()));
+        assert_eq!(chan.send(Due(Some(now)), "now"), Ok(()));
+
+        assert_eq!(chan.try_recv(), Ok("now"));
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(chan.next_due(), Some(later));
+        assert_eq!(
+            chan.recv(Some(Instant::now() + Duration::from_millis(10))),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert_eq!(chan.len(), 2);
+    }
+
+    #[test]
+    fn never_due_values_dont_keep_channel_open() {
+        let chan = Channel::new();
+        assert_eq!(chan.send(Due(None), 1), Ok(()));
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+        assert!(!chan.can_recv());
+        assert!(chan.disconnect());
+        assert!(chan.can_recv());
+        assert_eq!(chan.recv(None), Err(RecvTimeoutError::Disconnected));
+        assert_eq!(chan.len(), 1);
+    }
+}
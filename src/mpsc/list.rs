@@ -0,0 +1,587 @@
+//! Lock-free unbounded queue, a linked list of blocks holding `BLOCK_CAP` slots each.
+//!
+//! Senders claim slots by moving `tail` forward, receivers by moving `head` forward,
+//! following crossbeam's list flavor. A receiver can claim every ready slot of the
+//! current block with a single CAS, which is what feeds [`Receiver`](super::Receiver)'s buffer.
+
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering},
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{error::RecvError, waker::Operation, waker::Waiter};
+use crate::{
+    error::{RecvTimeoutError, SendError, TryRecvError},
+    utils::{Backoff, CachePadded},
+    waker::Waiters,
+};
+
+// slot state bits
+const WRITE: usize = 1;
+const READ: usize = 2;
+const DESTROY: usize = 4;
+
+// each block covers one lap of indices, the last index of a lap is a marker of moving to the next block
+const LAP: usize = 32;
+const BLOCK_CAP: usize = LAP - 1;
+// lower bits of an index are reserved for `MARK_BIT`
+const SHIFT: usize = 1;
+// in `head` means that the head block is not the last one, in `tail` means that channel is disconnected
+const MARK_BIT: usize = 1;
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    /// Waits until a sender that claimed this slot writes the value
+    fn wait_write(&self) {
+        let backoff = Backoff::new();
+        while self.state.load(Ordering::Acquire) & WRITE == 0 {
+            backoff.snooze();
+        }
+    }
+}
+
+struct Block<T> {
+    next: AtomicPtr<Block<T>>,
+    slots: [Slot<T>; BLOCK_CAP],
+}
+
+impl<T> Block<T> {
+    fn new() -> Box<Block<T>> {
+        // SAFETY: zeroed block is valid, `next` is null and no slot has `WRITE` set
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+
+    /// Waits until a sender installs the next block
+    fn wait_next(&self) -> *mut Block<T> {
+        let backoff = Backoff::new();
+        loop {
+            let next = self.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                return next;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Frees the block once every slot starting from `start` was read.
+    /// If a slot is still being read, its reader finishes the job instead
+    unsafe fn destroy(this: *mut Block<T>, start: usize) {
+        // last slot doesn't need `DESTROY`, its reader is the one who started destruction
+        for i in start..BLOCK_CAP - 1 {
+            let slot = (*this).slots.get_unchecked(i);
+            if slot.state.load(Ordering::Acquire) & READ == 0
+                && slot.state.fetch_or(DESTROY, Ordering::AcqRel) & READ == 0
+            {
+                return;
+            }
+        }
+        drop(Box::from_raw(this));
+    }
+}
+
+struct Position<T> {
+    index: AtomicUsize,
+    block: AtomicPtr<Block<T>>,
+}
+
+impl<T> Position<T> {
+    fn new() -> Self {
+        Position {
+            index: AtomicUsize::new(0),
+            block: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+/// Consecutive slots of one block claimed by a receiver
+struct Claim<T> {
+    block: *mut Block<T>,
+    offset: usize,
+    count: usize,
+}
+
+pub(crate) struct Channel<T> {
+    head: CachePadded<Position<T>>,
+    tail: CachePadded<Position<T>>,
+    /// receivers blocked on an empty channel
+    pub(crate) receivers: Waiters,
+    _marker: PhantomData<T>,
+}
+
+// values are only ever moved between threads, never shared
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    pub(crate) fn new() -> Self {
+        Channel {
+            head: CachePadded(Position::new()),
+            tail: CachePadded(Position::new()),
+            receivers: Waiters::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Claims a slot for sending, `None` if channel is disconnected
+    fn start_send(&self) -> Option<(*mut Block<T>, usize)> {
+        let backoff = Backoff::new();
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+
+        loop {
+            if tail & MARK_BIT != 0 {
+                return None;
+            }
+
+            let offset = (tail >> SHIFT) % LAP;
+
+            // another sender is installing the next block
+            if offset == BLOCK_CAP {
+                backoff.snooze();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // allocating in advance keeps other senders waiting for the next block as short as possible
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(Block::new());
+            }
+
+            // the very first value installs the first block
+            if block.is_null() {
+                let new = Box::into_raw(Block::new());
+                if self
+                    .tail
+                    .block
+                    .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                } else {
+                    next_block = unsafe { Some(Box::from_raw(new)) };
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+
+            let new_tail = tail + (1 << SHIFT);
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                new_tail,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    // claimed the last slot, so it's on us to install the next block
+                    if offset + 1 == BLOCK_CAP {
+                        let next_block = Box::into_raw(next_block.unwrap());
+                        self.tail.block.store(next_block, Ordering::Release);
+                        self.tail.index.fetch_add(1 << SHIFT, Ordering::Release);
+                        (*block).next.store(next_block, Ordering::Release);
+                    }
+                    return Some((block, offset));
+                },
+                Err(current) => {
+                    tail = current;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.write(value)?;
+        self.receivers.notify();
+        Ok(())
+    }
+
+    /// Sends every value and wakes up receivers once at the end. On disconnection returns
+    /// the value which failed to be sent followed by the ones not sent yet
+    pub(crate) fn send_all(
+        &self,
+        mut values: impl Iterator<Item = T>,
+    ) -> Result<(), SendError<Vec<T>>> {
+        let mut sent = 0;
+        let mut result = Ok(());
+        for value in values.by_ref() {
+            if let Err(SendError(value)) = self.write(value) {
+                result = Err(SendError(std::iter::once(value).chain(values).collect()));
+                break;
+            }
+            sent += 1;
+        }
+        match sent {
+            0 => {}
+            1 => self.receivers.notify(),
+            // mpmc receivers take one value at a time, so one of them isn't enough
+            _ => self.receivers.notify_all(),
+        }
+        result
+    }
+
+    /// Writes value into a claimed slot without waking anyone up
+    fn write(&self, value: T) -> Result<(), SendError<T>> {
+        let Some((block, offset)) = self.start_send() else {
+            return Err(SendError(value));
+        };
+        unsafe {
+            let slot = (*block).slots.get_unchecked(offset);
+            slot.value.get().write(MaybeUninit::new(value));
+            slot.state.fetch_or(WRITE, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Claims up to `limit` slots which were already claimed by senders
+    fn start_recv(&self, limit: usize) -> Result<Claim<T>, TryRecvError> {
+        let backoff = Backoff::new();
+        let mut head = self.head.index.load(Ordering::Acquire);
+        let mut block = self.head.block.load(Ordering::Acquire);
+
+        loop {
+            let offset = (head >> SHIFT) % LAP;
+
+            // another receiver is moving to the next block
+            if offset == BLOCK_CAP {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            let mut count = limit.min(BLOCK_CAP - offset);
+            let mut mark = head & MARK_BIT;
+            if mark == 0 {
+                atomic::fence(Ordering::SeqCst);
+                let tail = self.tail.index.load(Ordering::Relaxed);
+
+                if head >> SHIFT == tail >> SHIFT {
+                    return Err(match tail & MARK_BIT {
+                        0 => TryRecvError::Empty,
+                        _ => TryRecvError::Disconnected,
+                    });
+                }
+
+                if (head >> SHIFT) / LAP != (tail >> SHIFT) / LAP {
+                    // whole head block is claimed by senders
+                    mark = MARK_BIT;
+                } else {
+                    count = count.min((tail >> SHIFT) - (head >> SHIFT));
+                }
+            }
+
+            // the first block is still being installed
+            if block.is_null() {
+                backoff.snooze();
+                head = self.head.index.load(Ordering::Acquire);
+                block = self.head.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            let new_head = ((head & !MARK_BIT) + (count << SHIFT)) | mark;
+            match self.head.index.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    // claimed the rest of the block, move on to the next one
+                    if offset + count == BLOCK_CAP {
+                        let next = (*block).wait_next();
+                        let mut next_index = (new_head & !MARK_BIT).wrapping_add(1 << SHIFT);
+                        if !(*next).next.load(Ordering::Relaxed).is_null() {
+                            next_index |= MARK_BIT;
+                        }
+                        self.head.block.store(next, Ordering::Release);
+                        self.head.index.store(next_index, Ordering::Release);
+                    }
+                    return Ok(Claim {
+                        block,
+                        offset,
+                        count,
+                    });
+                },
+                Err(current) => {
+                    head = current;
+                    block = self.head.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    /// Reads claimed slots in order
+    unsafe fn read(&self, claim: Claim<T>, mut push: impl FnMut(T)) {
+        let block = claim.block;
+        for offset in claim.offset..claim.offset + claim.count {
+            let slot = (*block).slots.get_unchecked(offset);
+            slot.wait_write();
+            let value = slot.value.get().read().assume_init();
+
+            // reader of the last slot starts destruction of the block, everyone else
+            // continues it if destruction got stuck on their slot
+            if offset + 1 == BLOCK_CAP {
+                Block::destroy(block, 0);
+            } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+                Block::destroy(block, offset + 1);
+            }
+            push(value);
+        }
+    }
+
+    /// Returns the first available value and moves the rest of ready values of the block to `rest`
+    pub(crate) fn try_recv_batch(&self, rest: &mut VecDeque<T>) -> Result<T, TryRecvError> {
+        let claim = self.start_recv(usize::MAX)?;
+        let mut first = None;
+        unsafe {
+            self.read(claim, |value| match first {
+                None => first = Some(value),
+                Some(_) => rest.push_back(value),
+            })
+        };
+        Ok(first.expect("claim is never empty"))
+    }
+
+    /// Same as [`Channel::try_recv_batch`] but claims a single value, leaving the rest
+    /// to other receivers
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let claim = self.start_recv(1)?;
+        let mut value = None;
+        unsafe { self.read(claim, |claimed| value = Some(claimed)) };
+        Ok(value.expect("claim is never empty"))
+    }
+
+    /// Blocking version of [`Channel::try_recv_batch`]
+    pub(crate) fn recv_batch(
+        &self,
+        rest: &mut VecDeque<T>,
+        deadline: Option<Instant>,
+    ) -> Result<T, RecvTimeoutError> {
+        self.recv_with(deadline, || self.try_recv_batch(rest))
+    }
+
+    /// Blocking version of [`Channel::try_recv`]
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        self.recv_with(deadline, || self.try_recv())
+    }
+
+    /// Retries `attempt` while channel is empty, blocking in between
+    fn recv_with(
+        &self,
+        deadline: Option<Instant>,
+        mut attempt: impl FnMut() -> Result<T, TryRecvError>,
+    ) -> Result<T, RecvTimeoutError> {
+        loop {
+            match attempt() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            self.receivers
+                .wait(deadline, || !self.is_empty() || self.is_disconnected());
+        }
+    }
+
+    /// Async version of [`Channel::try_recv_batch`], registers the task as `oper` while channel is empty
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_recv_batch(
+        &self,
+        rest: &mut VecDeque<T>,
+        oper: Operation,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, RecvError>> {
+        match self.try_recv_batch(rest) {
+            Err(TryRecvError::Empty) => {}
+            result => return Poll::Ready(result.map_err(|_| RecvError)),
+        }
+        self.receivers
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        match self.try_recv_batch(rest) {
+            Err(TryRecvError::Empty) => Poll::Pending,
+            result => {
+                self.receivers.unregister(oper);
+                Poll::Ready(result.map_err(|_| RecvError))
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        loop {
+            let mut tail = self.tail.index.load(Ordering::SeqCst) >> SHIFT;
+            let mut head = self.head.index.load(Ordering::SeqCst) >> SHIFT;
+
+            // retrying until tail doesn't move while reading head
+            if self.tail.index.load(Ordering::SeqCst) >> SHIFT == tail {
+                // index at the end of a lap means the next block
+                if tail % LAP == BLOCK_CAP {
+                    tail = tail.wrapping_add(1);
+                }
+                if head % LAP == BLOCK_CAP {
+                    head = head.wrapping_add(1);
+                }
+                // counting from the start of the head block, skipping the end of lap indices
+                let start = head / LAP * LAP;
+                let tail = tail.wrapping_sub(start);
+                let head = head.wrapping_sub(start);
+                return tail - head - tail / LAP;
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        let head = self.head.index.load(Ordering::SeqCst);
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        head >> SHIFT == tail >> SHIFT
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.tail.index.load(Ordering::SeqCst) & MARK_BIT != 0
+    }
+
+    /// Stops accepting new values and wakes up blocked receivers.
+    /// Returns `false` if channel was already disconnected
+    pub(crate) fn disconnect(&self) -> bool {
+        let tail = self.tail.index.fetch_or(MARK_BIT, Ordering::SeqCst);
+        if tail & MARK_BIT == 0 {
+            self.receivers.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.index.get_mut() & !MARK_BIT;
+        let tail = *self.tail.index.get_mut() & !MARK_BIT;
+        let mut block = *self.head.block.get_mut();
+
+        unsafe {
+            // dropping values which were never received and freeing blocks on the way
+            while head != tail {
+                let offset = (head >> SHIFT) % LAP;
+                if offset < BLOCK_CAP {
+                    let slot = (*block).slots.get_unchecked(offset);
+                    (*slot.value.get()).assume_init_drop();
+                } else {
+                    let next = *(*block).next.get_mut();
+                    drop(Box::from_raw(block));
+                    block = next;
+                }
+                head = head.wrapping_add(1 << SHIFT);
+            }
+
+            if !block.is_null() {
+                drop(Box::from_raw(block));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn batch_stops_at_block_end() {
+        let chan = Channel::new();
+        for i in 0..BLOCK_CAP + 5 {
+            chan.send(i).unwrap();
+        }
+        let mut rest = VecDeque::new();
+        assert_eq!(chan.try_recv_batch(&mut rest), Ok(0));
+        assert_eq!(rest, (1..BLOCK_CAP).collect::<VecDeque<_>>());
+
+        rest.clear();
+        assert_eq!(chan.try_recv_batch(&mut rest), Ok(BLOCK_CAP));
+        assert_eq!(rest.len(), 4);
+        assert_eq!(chan.try_recv_batch(&mut rest), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn many_producers_keep_order() {
+        const THREADS: usize = if cfg!(miri) { 2 } else { 16 };
+        const VALUES: usize = if cfg!(miri) { 50 } else { 10_000 };
+
+        let chan = Arc::new(Channel::new());
+        let handles: Vec<_> = (0..THREADS)
+            .map(|producer| {
+                let chan = Arc::clone(&chan);
+                std::thread::spawn(move || {
+                    for i in 0..VALUES {
+                        chan.send((producer, i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let mut next = [0; THREADS];
+        let mut rest = VecDeque::new();
+        for _ in 0..THREADS * VALUES {
+            let (producer, i) = match rest.pop_front() {
+                Some(value) => value,
+                None => chan.recv_batch(&mut rest, None).unwrap(),
+            };
+            assert_eq!(next[producer], i);
+            next[producer] += 1;
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(rest.is_empty());
+        assert_eq!(chan.try_recv_batch(&mut rest), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn unreceived_values_are_dropped() {
+        let value = Arc::new(());
+        let chan = Channel::new();
+        for _ in 0..BLOCK_CAP * 3 {
+            chan.send(Arc::clone(&value)).unwrap();
+        }
+        let mut rest = VecDeque::new();
+        drop(chan.try_recv_batch(&mut rest));
+        drop(rest);
+        drop(chan);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn disconnect() {
+        let chan = Channel::new();
+        chan.send(1).unwrap();
+        assert!(chan.disconnect());
+        assert!(!chan.disconnect());
+        assert_eq!(chan.send(2), Err(SendError(2)));
+        let mut rest = VecDeque::new();
+        assert_eq!(chan.recv_batch(&mut rest, None), Ok(1));
+        assert_eq!(
+            chan.recv_batch(&mut rest, None),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
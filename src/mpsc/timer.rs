@@ -0,0 +1,205 @@
+//! Channels without senders which deliver on schedule.
+//!
+//! Nothing is sent ahead of time: a receiver checks whether the next delivery is due and
+//! sleeps until it is, so a blocking receiver costs no thread. A delivery makes a value
+//! out of the `Instant` it was due at, timers deliver that `Instant` itself.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{error::RecvError, waker::Alarm};
+use crate::{
+    error::{RecvTimeoutError, TryRecvError},
+    waker::Waiters,
+};
+
+enum Kind {
+    /// delivers once at `due`
+    After {
+        due: Instant,
+        is_fired: AtomicBool,
+    },
+    /// delivers at `next`, which then moves a `period` on, or a `period` past the
+    /// delivery if a slow receiver missed that
+    Tick {
+        next: Mutex<Instant>,
+        period: Duration,
+    },
+    Never,
+}
+
+pub(crate) struct Channel<T> {
+    kind: Kind,
+    /// makes the delivered value out of the time it was due
+    deliver: fn(Instant) -> T,
+    /// always empty, timers wake themselves up
+    pub(crate) receivers: Waiters,
+    /// wakes up a task once the next delivery is due
+    #[cfg(feature = "async")]
+    alarm: Alarm,
+}
+
+impl Channel<Instant> {
+    pub(crate) fn after(delay: Duration) -> Self {
+        // a deadline which can't be represented is never reached
+        Channel::new(
+            match Instant::now().checked_add(delay) {
+                Some(due) => Kind::After {
+                    due,
+                    is_fired: AtomicBool::new(false),
+                },
+                None => Kind::Never,
+            },
+            |due| due,
+        )
+    }
+
+    pub(crate) fn tick(period: Duration) -> Self {
+        Channel::new(
+            match Instant::now().checked_add(period) {
+                Some(next) => Kind::Tick {
+                    next: Mutex::new(next),
+                    period,
+                },
+                None => Kind::Never,
+            },
+            |due| due,
+        )
+    }
+}
+
+impl<T> Channel<T> {
+    pub(crate) fn never() -> Self {
+        Channel::new(Kind::Never, |_| unreachable!("never delivers"))
+    }
+
+    fn new(kind: Kind, deliver: fn(Instant) -> T) -> Self {
+        Channel {
+            kind,
+            deliver,
+            receivers: Waiters::new(),
+            #[cfg(feature = "async")]
+            alarm: Alarm::new(),
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        let now = Instant::now();
+        let delivered = match &self.kind {
+            Kind::After { due, is_fired } => {
+                if now < *due || is_fired.swap(true, Ordering::AcqRel) {
+                    return Err(TryRecvError::Empty);
+                }
+                *due
+            }
+            Kind::Tick { next, period } => {
+                let mut next = next.lock().unwrap();
+                if now < *next {
+                    return Err(TryRecvError::Empty);
+                }
+                let delivered = *next;
+                // skipped ticks of a slow receiver are not made up for
+                *next = match delivered.checked_add(*period) {
+                    Some(scheduled) if scheduled > now => scheduled,
+                    _ => now.checked_add(*period).unwrap_or(now),
+                };
+                delivered
+            }
+            Kind::Never => return Err(TryRecvError::Empty),
+        };
+        Ok((self.deliver)(delivered))
+    }
+
+    /// Blocking version of [`Channel::try_recv`]
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            if let Ok(value) = self.try_recv() {
+                return Ok(value);
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            match (self.due(), deadline) {
+                (Some(due), Some(deadline)) => {
+                    std::thread::sleep(due.min(deadline).saturating_duration_since(now))
+                }
+                (Some(wake_at), None) | (None, Some(wake_at)) => {
+                    std::thread::sleep(wake_at.saturating_duration_since(now))
+                }
+                (None, None) => std::thread::park(),
+            }
+        }
+    }
+
+    /// Async version of [`Channel::try_recv`], the alarm wakes the task up since there is
+    /// nothing else to do it
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        if let Ok(value) = self.try_recv() {
+            return Poll::Ready(Ok(value));
+        }
+        if let Some(due) = self.due() {
+            self.alarm.wake_at(due, cx.waker());
+        }
+        Poll::Pending
+    }
+
+    /// When the next value can be received, `None` if never
+    pub(crate) fn due(&self) -> Option<Instant> {
+        match &self.kind {
+            Kind::After { due, is_fired } => (!is_fired.load(Ordering::Acquire)).then_some(*due),
+            Kind::Tick { next, .. } => Some(*next.lock().unwrap()),
+            Kind::Never => None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.due().is_none_or(|due| Instant::now() < due)
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        match self.kind {
+            Kind::Never => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn after_fires_once() {
+        let chan = Channel::after(Duration::from_millis(10));
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+        let fired = chan.recv(None).unwrap();
+        assert!(Instant::now() >= fired);
+        assert!(chan.is_empty());
+        assert_eq!(
+            chan.recv(Some(Instant::now() + Duration::from_millis(10))),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn tick_skips_missed_deliveries() {
+        let chan = Channel::tick(Duration::from_millis(10));
+        let first = chan.recv(None).unwrap();
+        std::thread::sleep(Duration::from_millis(35));
+        let second = chan.try_recv().unwrap();
+        assert!(second >= first + Duration::from_millis(10));
+        // missed ticks are not queued up
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+    }
+}
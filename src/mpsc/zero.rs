@@ -0,0 +1,396 @@
+//! Zero-capacity queue, every value is handed over from a sender directly to a receiver.
+//!
+//! A sender offers its value and stays blocked until a receiver takes the offer.
+//! `try_send` can't wait, so it hands its value to a specific receiver which is already
+//! blocked instead. Offers are kept behind a mutex, blocked senders and receivers are
+//! parked in [`Waiters`].
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use crate::{error::RecvError, waker::Waiter};
+use crate::{
+    error::{RecvTimeoutError, SendError, TryRecvError, TrySendError},
+    waker::{Operation, Waiters},
+};
+
+/// Value waiting for a receiver, `oper` identifies the sender among [`Channel::senders`].
+/// `None` if no sender waits for it: the receiver it was handed to gave up
+struct Offer<T> {
+    oper: Option<Operation>,
+    value: T,
+}
+
+struct Inner<T> {
+    offers: VecDeque<Offer<T>>,
+    /// receivers blocked in `recv` or `poll_recv`, `try_send` hands its value to the first one
+    waiting: VecDeque<Operation>,
+    /// values handed over by `try_send`, by receiver
+    handed: Vec<(Operation, T)>,
+    is_disconnected: bool,
+}
+
+impl<T> Inner<T> {
+    /// Takes back the offer of `oper` if no receiver took it yet
+    fn withdraw(&mut self, oper: Operation) -> Option<T> {
+        let index = self
+            .offers
+            .iter()
+            .position(|offer| offer.oper == Some(oper))?;
+        self.offers.remove(index).map(|offer| offer.value)
+    }
+
+    fn is_offered(&self, oper: Operation) -> bool {
+        self.offers.iter().any(|offer| offer.oper == Some(oper))
+    }
+
+    /// Takes the value handed to receiver `oper`, or else the first offer
+    fn take(&mut self, oper: Option<Operation>) -> Option<Offer<T>> {
+        match oper.and_then(|oper| self.take_handed(oper)) {
+            Some(value) => Some(Offer { oper: None, value }),
+            None => self.offers.pop_front(),
+        }
+    }
+
+    fn take_handed(&mut self, oper: Operation) -> Option<T> {
+        let index = self.handed.iter().position(|(to, _)| *to == oper)?;
+        Some(self.handed.swap_remove(index).1)
+    }
+
+    fn stop_waiting(&mut self, oper: Operation) {
+        self.waiting.retain(|waiting| *waiting != oper);
+    }
+}
+
+pub(crate) struct Channel<T> {
+    inner: Mutex<Inner<T>>,
+    /// senders waiting for their offer to be taken
+    pub(crate) senders: Waiters,
+    /// receivers waiting for an offer
+    pub(crate) receivers: Waiters,
+}
+
+impl<T> Channel<T> {
+    pub(crate) fn new() -> Self {
+        Channel {
+            inner: Mutex::new(Inner {
+                offers: VecDeque::new(),
+                waiting: VecDeque::new(),
+                handed: Vec::new(),
+                is_disconnected: false,
+            }),
+            senders: Waiters::new(),
+            receivers: Waiters::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Blocks until a receiver takes the value
+    pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let oper = Operation::new();
+        self.offer(oper, value)?;
+
+        loop {
+            self.senders.wait_as(oper, None, || {
+                let inner = self.lock();
+                !inner.is_offered(oper) || inner.is_disconnected
+            });
+
+            let mut inner = self.lock();
+            if !inner.is_offered(oper) {
+                return Ok(());
+            }
+            if inner.is_disconnected {
+                return Err(SendError(inner.withdraw(oper).unwrap()));
+            }
+        }
+    }
+
+    /// Hands the value over to the first receiver blocked in `recv` or `poll_recv`,
+    /// fails with `Full` if there is none
+    pub(crate) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return Err(TrySendError::Disconnected(value));
+        }
+        let Some(receiver) = inner.waiting.pop_front() else {
+            return Err(TrySendError::Full(value));
+        };
+        inner.handed.push((receiver, value));
+        drop(inner);
+
+        self.receivers.notify_oper(receiver);
+        Ok(())
+    }
+
+    fn offer(&self, oper: Operation, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return Err(SendError(value));
+        }
+        inner.offers.push_back(Offer {
+            oper: Some(oper),
+            value,
+        });
+        drop(inner);
+
+        self.receivers.notify();
+        Ok(())
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv_as(None)
+    }
+
+    /// Same as [`Channel::try_recv`] but receiver `oper` gets the value handed to it first
+    fn try_recv_as(&self, oper: Option<Operation>) -> Result<T, TryRecvError> {
+        let mut inner = self.lock();
+        match inner.take(oper) {
+            Some(offer) => {
+                drop(inner);
+                if let Some(sender) = offer.oper {
+                    self.senders.notify_oper(sender);
+                }
+                Ok(offer.value)
+            }
+            None if inner.is_disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub(crate) fn recv(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let oper = Operation::new();
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            self.lock().waiting.push_back(oper);
+            self.receivers.wait_as(oper, deadline, || {
+                // senders selecting on this channel wait for a receiver to show up
+                self.senders.notify_all();
+                let inner = self.lock();
+                !inner.offers.is_empty() || !inner.handed.is_empty() || inner.is_disconnected
+            });
+
+            let mut inner = self.lock();
+            inner.stop_waiting(oper);
+            // `try_send` may have handed a value over right before the deadline
+            if let Some(value) = inner.take_handed(oper) {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Whether no offer can be taken, values handed to specific receivers aren't counted
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().offers.is_empty()
+    }
+
+    /// Offers and values handed to receivers which didn't take them yet
+    pub(crate) fn len(&self) -> usize {
+        let inner = self.lock();
+        inner.offers.len() + inner.handed.len()
+    }
+
+    /// Whether `try_send` would find a receiver to hand its value to
+    pub(crate) fn has_waiting_receivers(&self) -> bool {
+        !self.lock().waiting.is_empty()
+    }
+
+    /// Drops values no sender waits for. Blocked senders take their offers back themselves
+    pub(crate) fn drain(&self) {
+        let mut inner = self.lock();
+        let handed = std::mem::take(&mut inner.handed);
+        let (offers, orphaned): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut inner.offers)
+            .into_iter()
+            .partition(|offer| offer.oper.is_some());
+        inner.offers = offers;
+        drop(inner);
+
+        // values may run arbitrary code in `Drop`, so they are dropped outside the lock
+        drop(handed);
+        drop(orphaned);
+    }
+
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.lock().is_disconnected
+    }
+
+    /// Wakes up everyone blocked, senders whose offers weren't taken get their values back.
+    /// Returns `false` if channel was already disconnected
+    pub(crate) fn disconnect(&self) -> bool {
+        let mut inner = self.lock();
+        if inner.is_disconnected {
+            return false;
+        }
+        inner.is_disconnected = true;
+        drop(inner);
+
+        self.senders.notify_all();
+        self.receivers.notify_all();
+        true
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Channel<T> {
+    /// Async version of [`Channel::recv`], the task stays among receivers `try_send` can
+    /// hand values to until the value is taken or [`Channel::cancel_recv`] is called
+    pub(crate) fn poll_recv(
+        &self,
+        oper: Operation,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<T, RecvError>> {
+        match self.try_recv_as(Some(oper)) {
+            Err(TryRecvError::Empty) => {}
+            result => {
+                self.cancel_recv(oper);
+                return Poll::Ready(result.map_err(|_| RecvError));
+            }
+        }
+        self.receivers
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        let mut inner = self.lock();
+        if !inner.waiting.contains(&oper) {
+            inner.waiting.push_back(oper);
+        }
+        drop(inner);
+        // senders selecting on this channel wait for a receiver to show up
+        self.senders.notify_all();
+        match self.try_recv_as(Some(oper)) {
+            Err(TryRecvError::Empty) => Poll::Pending,
+            result => {
+                self.cancel_recv(oper);
+                Poll::Ready(result.map_err(|_| RecvError))
+            }
+        }
+    }
+
+    /// Stops waiting in [`Channel::poll_recv`]. A value already handed to `oper` goes back
+    /// to the channel for the next receiver
+    pub(crate) fn cancel_recv(&self, oper: Operation) {
+        let mut inner = self.lock();
+        inner.stop_waiting(oper);
+        if let Some(value) = inner.take_handed(oper) {
+            inner.offers.push_front(Offer { oper: None, value });
+        }
+        let has_offers = !inner.offers.is_empty();
+        drop(inner);
+
+        // a wakeup meant for this receiver goes to the next one
+        if !self.receivers.unregister(oper) && has_offers {
+            self.receivers.notify();
+        }
+    }
+
+    /// Offers `value` on the first poll and completes once a receiver takes it.
+    /// `queued` tells whether the offer of `oper` is waiting in the channel
+    pub(crate) fn poll_send(
+        &self,
+        value: &mut Option<T>,
+        oper: Operation,
+        queued: &mut bool,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), SendError<T>>> {
+        if !*queued {
+            let value = value.take().expect("value sent twice");
+            if let Err(err) = self.offer(oper, value) {
+                return Poll::Ready(Err(err));
+            }
+            *queued = true;
+        }
+
+        self.senders
+            .register(oper, Waiter::Task(cx.waker().clone()));
+        let mut inner = self.lock();
+        if !inner.is_offered(oper) {
+            *queued = false;
+            drop(inner);
+            self.senders.unregister(oper);
+            return Poll::Ready(Ok(()));
+        }
+        if inner.is_disconnected {
+            *queued = false;
+            let value = inner.withdraw(oper).unwrap();
+            drop(inner);
+            self.senders.unregister(oper);
+            return Poll::Ready(Err(SendError(value)));
+        }
+        Poll::Pending
+    }
+
+    /// Withdraws the offer of `oper` if no receiver took it yet
+    pub(crate) fn cancel_send(&self, oper: Operation, queued: &mut bool) {
+        if std::mem::take(queued) {
+            let value = self.lock().withdraw(oper);
+            self.senders.unregister(oper);
+            drop(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn try_send_needs_waiting_receiver() {
+        let chan = Arc::new(Channel::new());
+        assert_eq!(chan.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Empty));
+
+        let receiver = {
+            let chan = Arc::clone(&chan);
+            std::thread::spawn(move || chan.recv(None))
+        };
+        while !chan.has_waiting_receivers() {
+            std::thread::yield_now();
+        }
+        assert_eq!(chan.try_send(2), Ok(()));
+        assert_eq!(receiver.join().unwrap(), Ok(2));
+    }
+
+    #[test]
+    fn send_waits_for_receiver() {
+        let chan = Arc::new(Channel::new());
+        let sender = {
+            let chan = Arc::clone(&chan);
+            std::thread::spawn(move || chan.send(1))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!sender.is_finished());
+        assert_eq!(chan.recv(None), Ok(1));
+        assert_eq!(sender.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn disconnect_returns_offered_value() {
+        let chan = Arc::new(Channel::new());
+        let sender = {
+            let chan = Arc::clone(&chan);
+            std::thread::spawn(move || chan.send(1))
+        };
+        while chan.is_empty() {
+            std::thread::yield_now();
+        }
+        chan.disconnect();
+        assert_eq!(sender.join().unwrap(), Err(SendError(1)));
+        assert_eq!(chan.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
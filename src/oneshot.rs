@@ -0,0 +1,332 @@
+//! Channel for sending a single value
+//!
+//! Everything is synchronized through one atomic `state`: the value and the waiting receiver
+//! live in cells which are handed over between the sides by state transitions.
+
+use std::{
+    cell::UnsafeCell,
+    mem::{ManuallyDrop, MaybeUninit},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+
+use crate::waker::{Context, Operation, Selected, Waiter};
+
+pub use crate::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// Nothing happened yet
+const EMPTY: usize = 0;
+/// Receiver is blocked, `waiter` is set and owned by whoever changes the state next
+const WAITING: usize = 1;
+/// Value is sent and owned by the receiver
+const MESSAGE: usize = 2;
+/// Value is already received or one of the sides is gone
+const DISCONNECTED: usize = 3;
+
+struct Inner<T> {
+    state: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+    waiter: UnsafeCell<Option<Waiter>>,
+    /// registration of the receiver, used for selecting its `Context`
+    oper: Operation,
+}
+
+// access to the cells is serialized by `state`
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Returns `Err(SendError(value))` if receiver is dropped
+    pub fn send(self, value: T) -> Result<(), SendError<T>> {
+        // skipping `Drop`, the state is changed here instead
+        let this = ManuallyDrop::new(self);
+        let inner = unsafe { std::ptr::read(&this.inner) };
+
+        unsafe { inner.value.get().write(MaybeUninit::new(value)) };
+        match inner.state.swap(MESSAGE, Ordering::AcqRel) {
+            EMPTY => Ok(()),
+            WAITING => {
+                let waiter = unsafe { (*inner.waiter.get()).take() };
+                if let Some(waiter) = waiter {
+                    waiter.wake(inner.oper);
+                }
+                Ok(())
+            }
+            _ => {
+                // receiver is gone, so nobody else touches the value
+                inner.state.store(DISCONNECTED, Ordering::Relaxed);
+                let value = unsafe { inner.value.get().read().assume_init() };
+                Err(SendError(value))
+            }
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.state.swap(DISCONNECTED, Ordering::AcqRel) == WAITING {
+            let waiter = unsafe { (*self.inner.waiter.get()).take() };
+            if let Some(waiter) = waiter {
+                waiter.wake(self.inner.oper);
+            }
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Returns `Ok(value)` once value is sent (will block until then) or `Err(RecvError)`
+    /// if sender is dropped or value was already received
+    pub fn receive(&mut self) -> Result<T, RecvError> {
+        self.receive_until(None).map_err(|_| RecvError)
+    }
+
+    /// Same as [`Receiver::receive`] but returns `Err(TryRecvError::Empty)` instead of blocking
+    pub fn try_receive(&mut self) -> Result<T, TryRecvError> {
+        match self.inner.state.load(Ordering::Acquire) {
+            MESSAGE => Ok(self.take_value()),
+            DISCONNECTED => Err(TryRecvError::Disconnected),
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Same as [`Receiver::receive`] but gives up after waiting for `timeout`
+    pub fn receive_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // deadline may be too far in the future to ever be reached
+        self.receive_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Same as [`Receiver::receive`] but gives up once `deadline` is reached
+    pub fn receive_deadline(&mut self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.receive_until(Some(deadline))
+    }
+
+    fn receive_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_receive() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            let cx = Context::new();
+            if !self.register(Waiter::Thread(cx.clone())) {
+                continue;
+            }
+            if cx.wait_until(deadline) == Selected::Aborted {
+                // taking the waiter back, unless sender got it already
+                self.unregister();
+            }
+        }
+    }
+
+    /// Stores `waiter` to be woken up by sender. Returns `false` if sender got there first
+    fn register(&mut self, waiter: Waiter) -> bool {
+        self.unregister();
+        // sender may still be taking a previous waiter out of the cell
+        if self.inner.state.load(Ordering::Acquire) != EMPTY {
+            return false;
+        }
+        unsafe { *self.inner.waiter.get() = Some(waiter) };
+        match self
+            .inner
+            .state
+            .compare_exchange(EMPTY, WAITING, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => true,
+            Err(_) => {
+                unsafe { *self.inner.waiter.get() = None };
+                false
+            }
+        }
+    }
+
+    /// Drops the waiter stored by a previous [`Receiver::register`] if sender didn't take it
+    fn unregister(&mut self) {
+        if self
+            .inner
+            .state
+            .compare_exchange(WAITING, EMPTY, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            unsafe { *self.inner.waiter.get() = None };
+        }
+    }
+
+    /// Must be called in `MESSAGE` state only
+    fn take_value(&mut self) -> T {
+        let value = unsafe { self.inner.value.get().read().assume_init() };
+        self.inner.state.store(DISCONNECTED, Ordering::Release);
+        value
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Receiver<T> {
+    /// Async version of [`Receiver::receive`]
+    pub fn recv_async(&mut self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    fn poll_receive(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<T, RecvError>> {
+        loop {
+            match self.try_receive() {
+                Ok(value) => return Poll::Ready(Ok(value)),
+                Err(TryRecvError::Disconnected) => return Poll::Ready(Err(RecvError)),
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.register(Waiter::Task(cx.waker().clone())) {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Future returned by [`Receiver::recv_async`]
+#[cfg(feature = "async")]
+pub struct RecvFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_receive(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        match self.inner.state.swap(DISCONNECTED, Ordering::AcqRel) {
+            MESSAGE => unsafe { (*self.inner.value.get()).assume_init_drop() },
+            WAITING => unsafe { *self.inner.waiter.get() = None },
+            _ => {}
+        }
+    }
+}
+
+/// Creates a channel for sending a single value
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: AtomicUsize::new(EMPTY),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+        waiter: UnsafeCell::new(None),
+        oper: Operation::new(),
+    });
+    (
+        Sender {
+            inner: Arc::clone(&inner),
+        },
+        Receiver { inner },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let (tx, mut rx) = channel();
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Empty));
+        assert_eq!(tx.send(5), Ok(()));
+        assert_eq!(rx.receive(), Ok(5));
+        assert_eq!(rx.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn tx_closed() {
+        let (tx, mut rx) = channel::<()>();
+        let handle = std::thread::spawn(move || rx.receive());
+        std::thread::sleep(Duration::from_millis(10));
+        drop(tx);
+        assert_eq!(handle.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn rx_closed() {
+        let value = Arc::new(());
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(
+            tx.send(Arc::clone(&value)),
+            Err(SendError(Arc::clone(&value)))
+        );
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let (tx, mut rx) = channel();
+        assert_eq!(
+            rx.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx.send(1), Ok(()));
+        });
+        assert_eq!(rx.receive_timeout(Duration::from_secs(10)), Ok(1));
+    }
+
+    #[test]
+    fn unreceived_value_is_dropped() {
+        let value = Arc::new(());
+        let (tx, rx) = channel();
+        assert_eq!(tx.send(Arc::clone(&value)), Ok(()));
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn recv_async() {
+        use std::task::{Wake, Waker};
+
+        struct ThreadWaker(std::thread::Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let (tx, mut rx) = channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx.send(1), Ok(()));
+        });
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = TaskContext::from_waker(&waker);
+        let mut future = rx.recv_async();
+        loop {
+            match Pin::new(&mut future).poll(&mut cx) {
+                Poll::Ready(result) => break assert_eq!(result, Ok(1)),
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+}
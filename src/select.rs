@@ -0,0 +1,494 @@
+//! Waiting on several channel operations at once
+//!
+//! A blocked [`Select`] registers the same thread [`Context`] among waiters of every channel.
+//! Whichever channel selects the context first wakes it up, notifications from the others
+//! skip it and go to the next waiter, so no wakeup is lost.
+
+use std::time::{Duration, Instant};
+
+use crate::waker::{Context, Operation, Selected, Waiter, Waiters};
+
+pub(crate) mod sealed {
+    use std::time::Instant;
+
+    use crate::waker::Waiters;
+
+    /// One side of a channel which can be waited on
+    pub trait Handle {
+        /// Whether the operation would complete without blocking, including failing
+        /// because the channel is disconnected
+        fn is_ready(&self) -> bool;
+
+        /// Where blocked threads are registered, `None` if the operation never blocks
+        fn waiters(&self) -> Option<&Waiters>;
+
+        /// When the operation becomes ready without being notified, like a timer firing
+        fn deadline(&self) -> Option<Instant> {
+            None
+        }
+    }
+}
+
+/// Receivers which can be added to a [`Select`]
+pub trait SelectRecv: sealed::Handle {}
+
+/// Senders which can be added to a [`Select`]
+pub trait SelectSend: sealed::Handle {}
+
+/// Waits until one of several operations is ready
+///
+/// Operations are only checked for readiness, they have to be performed afterwards with
+/// `try_receive` or `try_send`. Another thread may complete the operation first, in which
+/// case it fails with `Empty` or `Full` and select should be repeated. [`select!`] does
+/// all of this.
+///
+/// [`select!`]: crate::select!
+pub struct Select<'a> {
+    handles: Vec<&'a dyn sealed::Handle>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Select {
+            handles: Vec::new(),
+        }
+    }
+
+    /// Adds a receive operation, returns its index
+    pub fn recv(&mut self, receiver: &'a impl SelectRecv) -> usize {
+        self.handles.push(receiver);
+        self.handles.len() - 1
+    }
+
+    /// Adds a send operation, returns its index
+    pub fn send(&mut self, sender: &'a impl SelectSend) -> usize {
+        self.handles.push(sender);
+        self.handles.len() - 1
+    }
+
+    /// Returns the index of the first ready operation, if any
+    pub fn try_ready(&mut self) -> Option<usize> {
+        self.handles.iter().position(|handle| handle.is_ready())
+    }
+
+    /// Blocks until one of the operations is ready and returns its index
+    ///
+    /// # Panics
+    ///
+    /// Panics if no operations were added
+    pub fn ready(&mut self) -> usize {
+        assert!(!self.handles.is_empty(), "no operations to select from");
+        self.ready_until(None).expect("waiting without a deadline")
+    }
+
+    /// Same as [`Select::ready`] but gives up after waiting for `timeout`
+    pub fn ready_timeout(&mut self, timeout: Duration) -> Option<usize> {
+        // deadline may be too far in the future to ever be reached
+        self.ready_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Same as [`Select::ready`] but gives up once `deadline` is reached
+    pub fn ready_deadline(&mut self, deadline: Instant) -> Option<usize> {
+        self.ready_until(Some(deadline))
+    }
+
+    fn ready_until(&mut self, deadline: Option<Instant>) -> Option<usize> {
+        loop {
+            if let Some(index) = self.try_ready() {
+                return Some(index);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+
+            let cx = Context::new();
+            let opers: Vec<_> = self
+                .handles
+                .iter()
+                .map(|handle| {
+                    let oper = Operation::new();
+                    if let Some(waiters) = handle.waiters() {
+                        waiters.register(oper, Waiter::Thread(cx.clone()));
+                    }
+                    oper
+                })
+                .collect();
+            // an operation may have become ready before registering
+            if self.handles.iter().any(|handle| handle.is_ready()) {
+                let _ = cx.try_select(Selected::Aborted);
+            }
+
+            // timers don't notify anyone, so wake up on time to recheck them
+            let wake_at = self
+                .handles
+                .iter()
+                .filter_map(|handle| handle.deadline())
+                .chain(deadline)
+                .min();
+            let selected = cx.wait_until(wake_at);
+            for (handle, oper) in self.handles.iter().zip(&opers) {
+                if let Some(waiters) = handle.waiters() {
+                    unregister(waiters, *oper, selected);
+                }
+            }
+            if let Selected::Operation(oper) = selected {
+                let index = opers.iter().position(|registered| *registered == oper);
+                return index;
+            }
+        }
+    }
+}
+
+/// Removes `oper` unless the channel already did it when selecting it
+fn unregister(waiters: &Waiters, oper: Operation, selected: Selected) {
+    if selected != Selected::Operation(oper) {
+        waiters.unregister(oper);
+    }
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Select::new()
+    }
+}
+
+/// Blocks until one of several channel operations completes and runs its arm
+///
+/// ```
+/// use yk_channel::{mpsc, select};
+///
+/// let (tx1, mut rx1) = mpsc::unbounded_channel::<i32>();
+/// let (tx2, mut rx2) = mpsc::bounded_channel::<&str>(1);
+/// tx2.send("hi").unwrap();
+///
+/// select! {
+///     recv(rx1) -> value => panic!("got {value:?}"),
+///     recv(rx2) -> value => assert_eq!(value, Ok("hi")),
+/// }
+/// select! {
+///     send(tx2, "again") -> result => assert_eq!(result, Ok(())),
+///     default => panic!("channel has room"),
+/// }
+/// # drop(tx1);
+/// ```
+///
+/// Supported arms:
+/// - `recv(receiver) -> result => body` with `result: Result<T, RecvError>`
+/// - `send(sender, value) -> result => body` with `result: Result<(), SendError<T>>`,
+///   `value` is evaluated once before waiting and dropped if another arm completes
+/// - `default => body` runs if nothing is ready right away
+/// - `default(timeout) => body` runs if nothing gets ready within `timeout`
+///
+/// Receivers and senders are used by reference, so they should be given as places
+/// like `rx` or `self.rx`. If several operations are ready, the first one wins.
+#[macro_export]
+macro_rules! select {
+    ($($tokens:tt)*) => {
+        $crate::__select_parse!(() () $($tokens)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_parse {
+    (($($arms:tt)*) ($($default:tt)*)) => {
+        $crate::__select_emit!(($($arms)*) ($($default)*))
+    };
+    (($($arms:tt)*) ($($default:tt)*) , $($rest:tt)*) => {
+        $crate::__select_parse!(($($arms)*) ($($default)*) $($rest)*)
+    };
+
+    (($($arms:tt)*) ($($default:tt)*) recv($rx:expr) -> $res:pat => $body:block $($rest:tt)*) => {
+        $crate::__select_parse!(($($arms)* (recv ($rx) ($res) ($body))) ($($default)*) $($rest)*)
+    };
+    (($($arms:tt)*) ($($default:tt)*) recv($rx:expr) -> $res:pat => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::__select_parse!(($($arms)* (recv ($rx) ($res) ($body))) ($($default)*) $($($rest)*)?)
+    };
+
+    // `value` is a fresh variable holding the value to send between attempts
+    (($($arms:tt)*) ($($default:tt)*) send($tx:expr, $val:expr) -> $res:pat => $body:block $($rest:tt)*) => {
+        $crate::__select_parse!(($($arms)* (send ($tx) (value) ($val) ($res) ($body))) ($($default)*) $($rest)*)
+    };
+    (($($arms:tt)*) ($($default:tt)*) send($tx:expr, $val:expr) -> $res:pat => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::__select_parse!(($($arms)* (send ($tx) (value) ($val) ($res) ($body))) ($($default)*) $($($rest)*)?)
+    };
+
+    (($($arms:tt)*) () default => $body:block $($rest:tt)*) => {
+        $crate::__select_parse!(($($arms)*) (try_ready ($body)) $($rest)*)
+    };
+    (($($arms:tt)*) () default => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::__select_parse!(($($arms)*) (try_ready ($body)) $($($rest)*)?)
+    };
+    (($($arms:tt)*) () default($timeout:expr) => $body:block $($rest:tt)*) => {
+        $crate::__select_parse!(($($arms)*) (ready_timeout ($body) $timeout) $($rest)*)
+    };
+    (($($arms:tt)*) () default($timeout:expr) => $body:expr $(, $($rest:tt)*)?) => {
+        $crate::__select_parse!(($($arms)*) (ready_timeout ($body) $timeout) $($($rest)*)?)
+    };
+}
+
+/// Each arm's result is wrapped in as many `Err`s as there are arms before it and breaks out
+/// of the retry loop, so that arm bodies run outside of it
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_emit {
+    (($($arm:tt)*) ($($default:tt)*)) => {{
+        $($crate::__select_bind!($arm);)*
+        let outcome = loop {
+            let index = {
+                let mut select = $crate::select::Select::new();
+                $($crate::__select_register!(select $arm);)*
+                $crate::__select_wait!(select $($default)*)
+            };
+            $crate::__select_try!(index () $($arm)*);
+        };
+        $crate::__select_match!(outcome () () $($arm)* ($($default)*))
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_bind {
+    ((recv $($args:tt)*)) => {};
+    ((send ($tx:expr) ($value:ident) ($val:expr) $($rest:tt)*)) => {
+        let mut $value = ::core::option::Option::Some($val);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_register {
+    ($select:ident (recv ($rx:expr) $($rest:tt)*)) => {
+        $select.recv(&$rx);
+    };
+    ($select:ident (send ($tx:expr) $($rest:tt)*)) => {
+        $select.send(&$tx);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_wait {
+    ($select:ident) => {
+        ::core::option::Option::Some($select.ready())
+    };
+    ($select:ident try_ready $body:tt) => {
+        $select.try_ready()
+    };
+    ($select:ident ready_timeout $body:tt $timeout:expr) => {
+        $select.ready_timeout($timeout)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_try {
+    ($index:ident ($($wrap:tt)*)) => {
+        if $index.is_none() {
+            break $crate::__select_wrap!(($($wrap)*) ());
+        }
+    };
+    ($index:ident ($($wrap:tt)*) (recv ($rx:expr) $($arm:tt)*) $($rest:tt)*) => {
+        if $index == ::core::option::Option::Some(0 $(+ $crate::__select_one!($wrap))*) {
+            match $rx.try_receive() {
+                ::core::result::Result::Ok(value) => {
+                    break $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(::core::result::Result::Ok(value)));
+                }
+                ::core::result::Result::Err($crate::mpsc::TryRecvError::Disconnected) => {
+                    break $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(::core::result::Result::Err($crate::mpsc::RecvError)));
+                }
+                // another receiver was faster
+                ::core::result::Result::Err($crate::mpsc::TryRecvError::Empty) => {}
+            }
+        }
+        $crate::__select_try!($index ($($wrap)* Err) $($rest)*)
+    };
+    ($index:ident ($($wrap:tt)*) (send ($tx:expr) ($value:ident) $($arm:tt)*) $($rest:tt)*) => {
+        if $index == ::core::option::Option::Some(0 $(+ $crate::__select_one!($wrap))*) {
+            match $tx.try_send($value.take().unwrap()) {
+                ::core::result::Result::Ok(()) => {
+                    break $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(::core::result::Result::Ok(())));
+                }
+                ::core::result::Result::Err($crate::mpsc::TrySendError::Disconnected(value)) => {
+                    break $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(::core::result::Result::Err($crate::mpsc::SendError(value))));
+                }
+                // another sender was faster
+                ::core::result::Result::Err($crate::mpsc::TrySendError::Full(value)) => {
+                    $value = ::core::option::Option::Some(value);
+                }
+            }
+        }
+        $crate::__select_try!($index ($($wrap)* Err) $($rest)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_match {
+    ($outcome:ident ($($wrap:tt)*) ($($arms:tt)*) ()) => {
+        match $outcome {
+            $($arms)*
+            $crate::__select_wrap!(($($wrap)*) ()) => unreachable!("no default arm"),
+        }
+    };
+    ($outcome:ident ($($wrap:tt)*) ($($arms:tt)*) ($kind:ident ($body:expr) $($timeout:tt)*)) => {
+        match $outcome {
+            $($arms)*
+            $crate::__select_wrap!(($($wrap)*) ()) => $body,
+        }
+    };
+    ($outcome:ident ($($wrap:tt)*) ($($arms:tt)*) (recv ($rx:expr) ($res:pat) ($body:expr)) $($rest:tt)*) => {
+        $crate::__select_match!($outcome ($($wrap)* Err) (
+            $($arms)*
+            $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(result)) => {
+                let $res = result;
+                $body
+            }
+        ) $($rest)*)
+    };
+    ($outcome:ident ($($wrap:tt)*) ($($arms:tt)*) (send ($tx:expr) $value:tt $val:tt ($res:pat) ($body:expr)) $($rest:tt)*) => {
+        $crate::__select_match!($outcome ($($wrap)* Err) (
+            $($arms)*
+            $crate::__select_wrap!(($($wrap)*) ::core::result::Result::Ok(result)) => {
+                let $res = result;
+                $body
+            }
+        ) $($rest)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_wrap {
+    (() $($value:tt)*) => {
+        $($value)*
+    };
+    ((Err $($wrap:tt)*) $($value:tt)*) => {
+        ::core::result::Result::Err($crate::__select_wrap!(($($wrap)*) $($value)*))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_one {
+    ($wrap:tt) => {
+        1
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mpmc, mpsc};
+
+    #[test]
+    fn ready_picks_ready_operation() {
+        let (tx1, rx1) = mpsc::unbounded_channel::<i32>();
+        let (tx2, rx2) = mpsc::bounded_channel::<i32>(1);
+        let mut select = Select::new();
+        assert_eq!(select.recv(&rx1), 0);
+        assert_eq!(select.recv(&rx2), 1);
+        assert_eq!(select.send(&tx2), 2);
+        assert_eq!(select.try_ready(), Some(2));
+
+        let mut select = Select::new();
+        select.recv(&rx1);
+        select.recv(&rx2);
+        assert_eq!(select.try_ready(), None);
+        assert_eq!(select.ready_timeout(Duration::from_millis(10)), None);
+        assert_eq!(tx1.send(1), Ok(()));
+        assert_eq!(select.ready(), 0);
+    }
+
+    #[test]
+    fn ready_wakes_up_on_send() {
+        let (tx1, rx1) = mpsc::unbounded_channel::<i32>();
+        let (_tx2, rx2) = mpmc::bounded_channel::<i32>(1);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(tx1.send(1), Ok(()));
+        });
+        let mut select = Select::new();
+        select.recv(&rx2);
+        select.recv(&rx1);
+        assert_eq!(select.ready(), 1);
+    }
+
+    #[test]
+    fn disconnected_is_ready() {
+        let (tx, rx) = mpsc::rendezvous_channel::<i32>();
+        drop(tx);
+        let mut select = Select::new();
+        select.recv(&rx);
+        assert_eq!(select.ready(), 0);
+    }
+
+    #[test]
+    fn macro_runs_ready_arm() {
+        let (tx1, mut rx1) = mpsc::unbounded_channel::<i32>();
+        let (tx2, mut rx2) = mpsc::bounded_channel::<&str>(1);
+        assert_eq!(tx1.send(1), Ok(()));
+
+        let got = crate::select! {
+            recv(rx2) -> value => panic!("unexpected {value:?}"),
+            recv(rx1) -> value => value,
+        };
+        assert_eq!(got, Ok(1));
+
+        crate::select! {
+            send(tx2, "hi") -> result => assert_eq!(result, Ok(())),
+            recv(rx1) -> value => panic!("unexpected {value:?}"),
+        }
+        let full = crate::select! {
+            send(tx2, "full") -> _ => false,
+            default => true,
+        };
+        assert!(full);
+        assert_eq!(rx2.try_receive(), Ok("hi"));
+
+        let timed_out = crate::select! {
+            recv(rx1) -> _ => false,
+            default(Duration::from_millis(10)) => { true }
+        };
+        assert!(timed_out);
+
+        drop(tx1);
+        crate::select! {
+            recv(rx1) -> value => assert_eq!(value, Err(mpsc::RecvError)),
+        }
+    }
+
+    #[test]
+    fn macro_wakes_up_for_timers() {
+        let (_tx, mut rx) = mpsc::unbounded_channel::<i32>();
+        let mut ticker = mpsc::tick(Duration::from_millis(10));
+        let mut timeout = mpsc::after(Duration::from_millis(35));
+        let mut disabled = mpsc::never::<i32>();
+        let mut ticks = 0;
+        loop {
+            crate::select! {
+                recv(rx) -> value => panic!("unexpected {value:?}"),
+                recv(disabled) -> value => panic!("unexpected {value:?}"),
+                recv(ticker) -> _ => ticks += 1,
+                recv(timeout) -> _ => break,
+            }
+        }
+        assert!(ticks >= 2);
+    }
+
+    #[test]
+    fn macro_waits_for_rendezvous() {
+        let (tx, mut rx) = mpsc::rendezvous_channel::<i32>();
+        let handle = std::thread::spawn(move || {
+            for i in 0..3 {
+                crate::select! {
+                    send(tx, i) -> result => assert_eq!(result, Ok(())),
+                }
+            }
+        });
+        for i in 0..3 {
+            assert_eq!(rx.receive(), Ok(i));
+        }
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    cell::Cell,
+    hint,
+    ops::{Deref, DerefMut},
+    thread,
+};
+
+/// Keeps the value on its own cache line so that atomics updated by senders
+/// and receivers don't slow each other down
+#[repr(align(128))]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+const SPIN_LIMIT: u32 = 6;
+const YIELD_LIMIT: u32 = 10;
+
+/// Exponential backoff for retrying lock-free operations
+pub(crate) struct Backoff {
+    step: Cell<u32>,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Backoff { step: Cell::new(0) }
+    }
+
+    /// Backs off after losing a race on a CAS
+    pub(crate) fn spin(&self) {
+        for _ in 0..1 << self.step.get().min(SPIN_LIMIT) {
+            hint::spin_loop();
+        }
+        if self.step.get() <= SPIN_LIMIT {
+            self.step.set(self.step.get() + 1);
+        }
+    }
+
+    /// Backs off while waiting for another thread to finish its part of an operation
+    pub(crate) fn snooze(&self) {
+        if self.step.get() <= SPIN_LIMIT {
+            for _ in 0..1 << self.step.get() {
+                hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+        if self.step.get() <= YIELD_LIMIT {
+            self.step.set(self.step.get() + 1);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! Parking of threads (and tasks) blocked on a channel until the other side wakes them up
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::OnceLock,
+    task::{self, Poll, Waker},
+};
+
+/// Identifies a single registration in [`Waiters`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Operation(usize);
+
+impl Operation {
+    /// Returns an id no other operation uses
+    pub(crate) fn new() -> Operation {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        Operation(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// State of a [`Context`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Selected {
+    Waiting,
+    /// gave up waiting, e.g. because of a timeout
+    Aborted,
+    /// woken up by the channel where `Operation` was registered
+    Operation(Operation),
+}
+
+impl From<usize> for Selected {
+    fn from(value: usize) -> Self {
+        match value {
+            0 => Selected::Waiting,
+            1 => Selected::Aborted,
+            id => Selected::Operation(Operation(id - 2)),
+        }
+    }
+}
+
+impl From<Selected> for usize {
+    fn from(selected: Selected) -> Self {
+        match selected {
+            Selected::Waiting => 0,
+            Selected::Aborted => 1,
+            Selected::Operation(Operation(id)) => id.wrapping_add(2),
+        }
+    }
+}
+
+/// Blocked thread. It is woken up at most once: whoever selects it first wins
+#[derive(Clone)]
+pub(crate) struct Context {
+    inner: Arc<ContextInner>,
+}
+
+struct ContextInner {
+    selected: AtomicUsize,
+    lock: Mutex<()>,
+    unparked: Condvar,
+}
+
+impl Context {
+    pub(crate) fn new() -> Context {
+        Context {
+            inner: Arc::new(ContextInner {
+                selected: AtomicUsize::new(Selected::Waiting.into()),
+                lock: Mutex::new(()),
+                unparked: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Selects the context unless something else was selected first
+    pub(crate) fn try_select(&self, selected: Selected) -> Result<(), Selected> {
+        self.inner
+            .selected
+            .compare_exchange(
+                Selected::Waiting.into(),
+                selected.into(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .map(|_| ())
+            .map_err(Selected::from)
+    }
+
+    pub(crate) fn selected(&self) -> Selected {
+        self.inner.selected.load(Ordering::Acquire).into()
+    }
+
+    pub(crate) fn unpark(&self) {
+        let _guard = self.inner.lock.lock().unwrap();
+        self.inner.unparked.notify_one();
+    }
+
+    /// Blocks until the context is selected. Once `deadline` passes the context aborts itself
+    pub(crate) fn wait_until(&self, deadline: Option<Instant>) -> Selected {
+        let mut guard = self.inner.lock.lock().unwrap();
+        loop {
+            let selected = self.selected();
+            if selected != Selected::Waiting {
+                return selected;
+            }
+            match deadline {
+                None => guard = self.inner.unparked.wait(guard).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return match self.try_select(Selected::Aborted) {
+                            Ok(()) => Selected::Aborted,
+                            Err(selected) => selected,
+                        };
+                    }
+                    guard = self
+                        .inner
+                        .unparked
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) enum Waiter {
+    Thread(Context),
+    #[cfg(feature = "async")]
+    Task(Waker),
+}
+
+impl Waiter {
+    /// Wakes up the waiter unless its thread was already selected by something else
+    pub(crate) fn wake(self, oper: Operation) {
+        let entry = Entry { oper, waiter: self };
+        if entry.try_select() {
+            entry.wake();
+        }
+    }
+}
+
+struct Entry {
+    oper: Operation,
+    waiter: Waiter,
+}
+
+impl Entry {
+    /// Claims the waiter for this entry, fails if a thread is already busy with another operation
+    fn try_select(&self) -> bool {
+        match &self.waiter {
+            Waiter::Thread(cx) => cx.try_select(Selected::Operation(self.oper)).is_ok(),
+            #[cfg(feature = "async")]
+            Waiter::Task(_) => true,
+        }
+    }
+
+    fn wake(self) {
+        match self.waiter {
+            Waiter::Thread(cx) => cx.unpark(),
+            #[cfg(feature = "async")]
+            Waiter::Task(waker) => waker.wake(),
+        }
+    }
+}
+
+/// Queue of threads and tasks waiting for one side of a channel, woken in FIFO order
+///
+/// Public only to appear in the sealed [`crate::select::sealed::Handle`], the module is private
+pub struct Waiters {
+    entries: Mutex<VecDeque<Entry>>,
+    /// lets `notify` skip locking while nobody waits
+    is_empty: AtomicBool,
+}
+
+impl Waiters {
+    pub(crate) fn new() -> Self {
+        Waiters {
+            entries: Mutex::new(VecDeque::new()),
+            is_empty: AtomicBool::new(true),
+        }
+    }
+
+    /// Queues `waiter` or replaces the waiter of already queued `oper` keeping its turn
+    pub(crate) fn register(&self, oper: Operation, waiter: Waiter) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|entry| entry.oper == oper) {
+            Some(entry) => entry.waiter = waiter,
+            None => entries.push_back(Entry { oper, waiter }),
+        }
+        self.is_empty.store(false, Ordering::SeqCst);
+    }
+
+    /// Same as [`Waiters::register`] but a new entry goes to the front of the queue.
+    /// Used by a task which was woken up but lost the race, so that it keeps its turn
+    #[cfg(feature = "async")]
+    pub(crate) fn register_first(&self, oper: Operation, waiter: Waiter) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter_mut().find(|entry| entry.oper == oper) {
+            Some(entry) => entry.waiter = waiter,
+            None => entries.push_front(Entry { oper, waiter }),
+        }
+        self.is_empty.store(false, Ordering::SeqCst);
+    }
+
+    /// Removes `oper` from the queue, returns `false` if it was already woken up
+    pub(crate) fn unregister(&self, oper: Operation) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|entry| entry.oper == oper);
+        if let Some(index) = index {
+            entries.remove(index);
+        }
+        self.is_empty.store(entries.is_empty(), Ordering::SeqCst);
+        index.is_some()
+    }
+
+    /// Wakes up the first waiter that isn't busy elsewhere
+    pub(crate) fn notify(&self) {
+        if self.is_empty.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = match entries.iter().position(Entry::try_select) {
+            Some(index) => entries.remove(index),
+            None => None,
+        };
+        self.is_empty.store(entries.is_empty(), Ordering::SeqCst);
+        drop(entries);
+
+        if let Some(entry) = entry {
+            entry.wake();
+        }
+    }
+
+    /// Wakes up `oper` if it is still queued
+    pub(crate) fn notify_oper(&self, oper: Operation) {
+        if self.is_empty.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let entry = match entries.iter().position(|entry| entry.oper == oper) {
+            Some(index) if entries[index].try_select() => entries.remove(index),
+            _ => None,
+        };
+        self.is_empty.store(entries.is_empty(), Ordering::SeqCst);
+        drop(entries);
+
+        if let Some(entry) = entry {
+            entry.wake();
+        }
+    }
+
+    /// Wakes up everybody, used once the channel is disconnected
+    pub(crate) fn notify_all(&self) {
+        if self.is_empty.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let drained = std::mem::take(&mut *entries);
+        self.is_empty.store(true, Ordering::SeqCst);
+        drop(entries);
+
+        for entry in drained {
+            if entry.try_select() {
+                entry.wake();
+            }
+        }
+    }
+
+    /// Parks current thread until it is notified or `deadline` passes.
+    /// `is_ready` is checked after registering so that a wakeup can't be missed
+    pub(crate) fn wait(&self, deadline: Option<Instant>, is_ready: impl FnOnce() -> bool) {
+        self.wait_as(Operation::new(), deadline, is_ready)
+    }
+
+    /// Same as [`Waiters::wait`] but registers as `oper`, so that [`Waiters::notify_oper`] can find it
+    pub(crate) fn wait_as(
+        &self,
+        oper: Operation,
+        deadline: Option<Instant>,
+        is_ready: impl FnOnce() -> bool,
+    ) {
+        let cx = Context::new();
+        self.register(oper, Waiter::Thread(cx.clone()));
+        if is_ready() {
+            let _ = cx.try_select(Selected::Aborted);
+        }
+        if cx.wait_until(deadline) != Selected::Operation(oper) {
+            self.unregister(oper);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Waiters {
+    /// Runs `attempt` until it succeeds, registering the task as `oper` in between.
+    /// `queued` tells whether `oper` was registered by a previous poll: a task which was
+    /// woken up but lost the race to another one keeps its turn
+    pub(crate) fn poll<R>(
+        &self,
+        oper: Operation,
+        queued: &mut bool,
+        cx: &mut task::Context<'_>,
+        mut attempt: impl FnMut() -> Option<R>,
+    ) -> Poll<R> {
+        if let Some(result) = attempt() {
+            if std::mem::take(queued) {
+                self.unregister(oper);
+            }
+            return Poll::Ready(result);
+        }
+        let waiter = Waiter::Task(cx.waker().clone());
+        match *queued {
+            true => self.register_first(oper, waiter),
+            false => self.register(oper, waiter),
+        }
+        *queued = true;
+        match attempt() {
+            None => Poll::Pending,
+            Some(result) => {
+                *queued = false;
+                self.unregister(oper);
+                Poll::Ready(result)
+            }
+        }
+    }
+
+    /// Gives up the turn of `oper` registered by [`Waiters::poll`]. If it was already woken up,
+    /// the wakeup goes to the next waiter instead of getting lost
+    pub(crate) fn cancel(&self, oper: Operation, queued: &mut bool) {
+        if std::mem::take(queued) && !self.unregister(oper) {
+            self.notify();
+        }
+    }
+}
+
+/// Wakes up a task once a deadline passes, for channels where no sender would do it
+///
+/// Deadlines of every alarm go to a single process-wide thread, started on first use, which
+/// sleeps until the earliest one. An alarm has at most one wakeup pending
+#[cfg(feature = "async")]
+pub(crate) struct Alarm {
+    id: usize,
+}
+
+#[cfg(feature = "async")]
+static TIMER: OnceLock<Timer> = OnceLock::new();
+
+#[cfg(feature = "async")]
+struct Timer {
+    state: Mutex<TimerState>,
+    changed: Condvar,
+}
+
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct TimerState {
+    /// pending wakeups, earliest first, alarm ids tell equal deadlines apart
+    wakeups: BTreeMap<(Instant, usize), Waker>,
+    /// deadline of the pending wakeup of each alarm
+    deadlines: HashMap<usize, Instant>,
+}
+
+#[cfg(feature = "async")]
+impl Alarm {
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        Alarm {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Wakes `waker` up at `deadline`, replacing the wakeup requested before
+    pub(crate) fn wake_at(&self, deadline: Instant, waker: &Waker) {
+        let timer = TIMER.get_or_init(|| {
+            std::thread::spawn(|| TIMER.wait().run());
+            Timer {
+                state: Mutex::new(TimerState::default()),
+                changed: Condvar::new(),
+            }
+        });
+        let mut state = timer.state.lock().unwrap();
+        if let Some(previous) = state.deadlines.insert(self.id, deadline) {
+            state.wakeups.remove(&(previous, self.id));
+        }
+        state.wakeups.insert((deadline, self.id), waker.clone());
+        let is_earliest = state
+            .wakeups
+            .first_key_value()
+            .is_some_and(|(&(_, id), _)| id == self.id);
+        drop(state);
+
+        // the timer thread only needs to recheck if it sleeps past the new deadline
+        if is_earliest {
+            timer.changed.notify_one();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Timer {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let Some(&(deadline, _)) = state.wakeups.first_key_value().map(|(key, _)| key) else {
+                state = self.changed.wait(state).unwrap();
+                continue;
+            };
+            let now = Instant::now();
+            if now < deadline {
+                state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+            let ((_, id), waker) = state.wakeups.pop_first().unwrap();
+            state.deadlines.remove(&id);
+            drop(state);
+            waker.wake();
+            state = self.state.lock().unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for Alarm {
+    fn drop(&mut self) {
+        if let Some(timer) = TIMER.get() {
+            let mut state = timer.state.lock().unwrap();
+            if let Some(deadline) = state.deadlines.remove(&self.id) {
+                state.wakeups.remove(&(deadline, self.id));
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Watch channel, holds only the latest value
+//!
+//! The value lives behind a `RwLock` together with a version counter bumped on every send.
+//! Each receiver remembers the last version it has seen, so it can wait for the next change.
+
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock, RwLockReadGuard,
+    },
+};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::waker::Waiters;
+#[cfg(feature = "async")]
+use crate::waker::{Operation, Waiter};
+
+pub use crate::error::{RecvError, SendError};
+
+struct Shared<T> {
+    value: RwLock<T>,
+    /// bumped while `value` is locked for writing
+    version: AtomicUsize,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    /// set once all senders are gone
+    is_closed: AtomicBool,
+    /// receivers waiting for a change
+    waiters: Waiters,
+}
+
+/// Read access to the value held by the channel, senders are blocked while it exists
+pub struct Ref<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value, returns `Err(SendError(value))` if there are no receivers
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(SendError(value));
+        }
+        self.send_replace(value);
+        Ok(())
+    }
+
+    /// Replaces the value even if there are no receivers, returns the previous one
+    pub fn send_replace(&self, value: T) -> T {
+        let mut guard = self.shared.value.write().unwrap();
+        let old = std::mem::replace(&mut *guard, value);
+        self.shared.version.fetch_add(1, Ordering::Release);
+        drop(guard);
+
+        self.shared.waiters.notify_all();
+        old
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.value.read().unwrap(),
+        }
+    }
+
+    /// Creates a receiver which has already seen the current value
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        let seen = self.shared.version.load(Ordering::Acquire);
+        Receiver::new(Arc::clone(&self.shared), seen)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // notifying receivers to stop waiting if this was the last sender
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.is_closed.store(true, Ordering::SeqCst);
+            self.shared.waiters.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// version of the last value marked as seen
+    seen: usize,
+    /// registration among waiting receivers while waiting in `poll`
+    #[cfg(feature = "async")]
+    oper: Operation,
+}
+
+impl<T> Receiver<T> {
+    fn new(shared: Arc<Shared<T>>, seen: usize) -> Self {
+        Receiver {
+            shared,
+            seen,
+            #[cfg(feature = "async")]
+            oper: Operation::new(),
+        }
+    }
+
+    /// Returns the current value without marking it as seen
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.value.read().unwrap(),
+        }
+    }
+
+    /// Returns the current value and marks it as seen
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let guard = self.shared.value.read().unwrap();
+        // version can't change while the value is locked
+        self.seen = self.shared.version.load(Ordering::Acquire);
+        Ref { guard }
+    }
+
+    /// Returns whether there is a value which wasn't seen yet, or `Err(RecvError)` if
+    /// all senders are gone
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        if self.shared.is_closed.load(Ordering::SeqCst) {
+            return Err(RecvError);
+        }
+        Ok(self.shared.version.load(Ordering::Acquire) != self.seen)
+    }
+
+    /// Blocks until there is a value which wasn't seen yet and marks it as seen.
+    /// Returns `Err(RecvError)` if all senders are gone and there's nothing new
+    pub fn changed(&mut self) -> Result<(), RecvError> {
+        loop {
+            if let Some(result) = self.try_changed() {
+                return result;
+            }
+            self.shared.waiters.wait(None, || {
+                self.shared.version.load(Ordering::Acquire) != self.seen
+                    || self.shared.is_closed.load(Ordering::SeqCst)
+            });
+        }
+    }
+
+    fn try_changed(&mut self) -> Option<Result<(), RecvError>> {
+        // closing is checked first, so that the last value sent before it isn't missed
+        let is_closed = self.shared.is_closed.load(Ordering::SeqCst);
+        let version = self.shared.version.load(Ordering::Acquire);
+        if version != self.seen {
+            self.seen = version;
+            return Some(Ok(()));
+        }
+        is_closed.then_some(Err(RecvError))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Receiver<T> {
+    /// Async version of [`Receiver::changed`]
+    pub fn changed_async(&mut self) -> ChangedFuture<'_, T> {
+        ChangedFuture { receiver: self }
+    }
+
+    fn poll_changed(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), RecvError>> {
+        if let Some(result) = self.try_changed() {
+            return Poll::Ready(result);
+        }
+        self.shared
+            .waiters
+            .register(self.oper, Waiter::Task(cx.waker().clone()));
+        match self.try_changed() {
+            None => Poll::Pending,
+            Some(result) => {
+                self.shared.waiters.unregister(self.oper);
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+/// Future returned by [`Receiver::changed_async`]
+#[cfg(feature = "async")]
+pub struct ChangedFuture<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for ChangedFuture<'_, T> {
+    type Output = Result<(), RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_changed(cx)
+    }
+}
+
+/// New receiver has seen the same values as this one
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver::new(Arc::clone(&self.shared), self.seen)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "async")]
+        self.shared.waiters.unregister(self.oper);
+        self.shared.receivers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Creates a watch channel holding `initial`, which counts as already seen by the receiver
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(initial),
+        version: AtomicUsize::new(0),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        is_closed: AtomicBool::new(false),
+        waiters: Waiters::new(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver::new(shared, 0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receiver_sees_latest_value() {
+        let (tx, mut rx) = channel(0);
+        assert_eq!(*rx.borrow(), 0);
+        assert_eq!(rx.has_changed(), Ok(false));
+
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(tx.send_replace(2), 1);
+        assert_eq!(rx.has_changed(), Ok(true));
+        assert_eq!(rx.changed(), Ok(()));
+        assert_eq!(*rx.borrow(), 2);
+        assert_eq!(rx.has_changed(), Ok(false));
+    }
+
+    #[test]
+    fn changed_waits_for_send() {
+        let (tx, mut rx) = channel(0);
+        let handle = std::thread::spawn(move || {
+            assert_eq!(rx.changed(), Ok(()));
+            *rx.borrow_and_update()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(tx.send(1), Ok(()));
+        assert_eq!(handle.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn closed() {
+        let (tx, mut rx) = channel(0);
+        assert_eq!(tx.send(1), Ok(()));
+        drop(tx);
+        // last value is still reported before closing
+        assert_eq!(rx.changed(), Ok(()));
+        assert_eq!(rx.changed(), Err(RecvError));
+        assert_eq!(*rx.borrow(), 1);
+
+        let (tx, rx) = channel(0);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+        assert_eq!(tx.send_replace(2), 0);
+        assert_eq!(*tx.subscribe().borrow(), 2);
+    }
+}